@@ -0,0 +1,151 @@
+//! Compares the public owned decoding paths with the zero-copy decoder on the same synthetic
+//! torrents, and all of them with the original iterator-based decoder on a small one
+
+use bittorrent_starter_rust::bencode::{
+    decode_bencoded_structure, decode_borrowed, decode_with_options, encode_bencoded_structure,
+    BValue, DecodeOptions, Mode,
+};
+use criterion::measurement::WallTime;
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput,
+};
+use std::collections::BTreeMap;
+
+/// The decoder this crate started with: it walks a `Peekable<IntoIter<u8>>` and clones the
+/// iterator, with all the input left in it, to read every length prefix
+mod original {
+    use bittorrent_starter_rust::bencode::BValue;
+    use std::collections::BTreeMap;
+    use std::iter::Peekable;
+    use std::vec::IntoIter;
+
+    type Bytes = Peekable<IntoIter<u8>>;
+
+    pub fn decode_bencoded_structure(encoded_value: Vec<u8>) -> Result<BValue, &'static str> {
+        parse_bencoded_values(&mut encoded_value.into_iter().peekable())
+    }
+
+    fn parse_bencoded_values(bytes: &mut Bytes) -> Result<BValue, &'static str> {
+        let num_str: String = bytes
+            .clone()
+            .take_while(|c| c.is_ascii_digit())
+            .map(|c| c as char)
+            .collect();
+        if let Ok(num) = num_str.parse::<usize>() {
+            return parse_bencoded_string(bytes, num);
+        }
+        match bytes.next() {
+            Some(b'i') => {
+                let number: String = bytes.take_while(|&c| c != b'e').map(char::from).collect();
+                number
+                    .parse()
+                    .map(BValue::Int)
+                    .map_err(|_| "Invalid number")
+            }
+            Some(b'l') => {
+                let mut list = Vec::new();
+                while bytes.next_if_eq(&b'e').is_none() {
+                    list.push(parse_bencoded_values(bytes)?);
+                }
+                Ok(BValue::List(list))
+            }
+            Some(b'd') => {
+                let mut map = BTreeMap::new();
+                while bytes.next_if_eq(&b'e').is_none() {
+                    let BValue::Bytes(key) = parse_bencoded_values(bytes)? else {
+                        return Err("Key wasn't a string!");
+                    };
+                    map.insert(key, parse_bencoded_values(bytes)?);
+                }
+                Ok(BValue::Dict(map))
+            }
+            _ => Err("Unable to parse bencoded values"),
+        }
+    }
+
+    fn parse_bencoded_string(bytes: &mut Bytes, length: usize) -> Result<BValue, &'static str> {
+        bytes.find(|&c| c == b':').ok_or("Bad string")?;
+        let data: Vec<u8> = bytes.take(length).collect();
+        match data.len() == length {
+            true => Ok(BValue::Bytes(data)),
+            false => Err("String shorter than its length prefix"),
+        }
+    }
+}
+
+fn bytes(s: &str) -> BValue {
+    BValue::Bytes(s.as_bytes().to_vec())
+}
+
+/// A multi-file torrent with `pieces` piece hashes and `files` file entries
+fn torrent(pieces: usize, files: usize) -> Vec<u8> {
+    let files = (0..files)
+        .map(|i| {
+            let mut file = BTreeMap::new();
+            file.insert(b"length".to_vec(), BValue::Int(i as i64 * 4096));
+            file.insert(
+                b"path".to_vec(),
+                BValue::List(vec![bytes("dir"), bytes(&format!("file-{i}.bin"))]),
+            );
+            BValue::Dict(file)
+        })
+        .collect();
+
+    let mut info = BTreeMap::new();
+    info.insert(b"files".to_vec(), BValue::List(files));
+    info.insert(b"name".to_vec(), bytes("large"));
+    info.insert(b"piece length".to_vec(), BValue::Int(262_144));
+    info.insert(
+        b"pieces".to_vec(),
+        BValue::Bytes((0..pieces * 20).map(|i| i as u8).collect()),
+    );
+
+    let mut torrent = BTreeMap::new();
+    torrent.insert(
+        b"announce".to_vec(),
+        bytes("http://tracker.example/announce"),
+    );
+    torrent.insert(b"info".to_vec(), BValue::Dict(info));
+    encode_bencoded_structure(&BValue::Dict(torrent))
+}
+
+/// The current decoders, each given the same input
+fn bench_current(group: &mut BenchmarkGroup<WallTime>, torrent: &[u8]) {
+    group.throughput(Throughput::Bytes(torrent.len() as u64));
+    group.bench_function("decode_bencoded_structure", |b| {
+        b.iter(|| decode_bencoded_structure(black_box(torrent)).unwrap())
+    });
+    let strict = DecodeOptions {
+        mode: Mode::Strict,
+        ..DecodeOptions::default()
+    };
+    group.bench_function("decode_with_options_strict", |b| {
+        b.iter(|| decode_with_options(black_box(torrent), strict).unwrap())
+    });
+    group.bench_function("decode_borrowed", |b| {
+        b.iter(|| decode_borrowed(black_box(torrent)).unwrap())
+    });
+}
+
+fn decode(c: &mut Criterion) {
+    // The original decoder is quadratic in the input size, so it only gets the small torrent
+    let small = torrent(1_000, 100);
+    assert_eq!(
+        original::decode_bencoded_structure(small.clone()),
+        Ok(decode_bencoded_structure(&small).unwrap())
+    );
+    let mut group = c.benchmark_group("decode_small_torrent");
+    bench_current(&mut group, &small);
+    group.bench_function("original", |b| {
+        b.iter(|| original::decode_bencoded_structure(black_box(small.clone())).unwrap())
+    });
+    group.finish();
+
+    let large = torrent(100_000, 5_000);
+    let mut group = c.benchmark_group("decode_large_torrent");
+    bench_current(&mut group, &large);
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
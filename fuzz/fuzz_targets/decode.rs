@@ -0,0 +1,28 @@
+//! Every decoder entry point must return an error rather than panic, and they must agree
+#![no_main]
+
+use bittorrent_starter_rust::bencode::{
+    decode_bencoded_structure, decode_borrowed, decode_document, Mode, Progress, StreamDecoder,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let owned = decode_bencoded_structure(data);
+    let borrowed = decode_borrowed(data);
+    if let (Ok(owned), Ok(borrowed)) = (&owned, &borrowed) {
+        assert_eq!(owned, &borrowed.to_owned_value());
+    }
+
+    if decode_document(data, Mode::Strict).is_ok() {
+        assert!(owned.is_ok());
+    }
+
+    let mut stream = StreamDecoder::new();
+    for byte in data {
+        stream.feed(std::slice::from_ref(byte));
+        match stream.decode() {
+            Ok(Progress::NeedMore(n)) => assert!(n > 0),
+            Ok(Progress::Complete(..)) | Err(_) => break,
+        }
+    }
+});
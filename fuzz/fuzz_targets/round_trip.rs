@@ -0,0 +1,21 @@
+//! decode -> encode -> decode must be lossless, and canonical input must re-encode byte for byte
+#![no_main]
+
+use bittorrent_starter_rust::bencode::{
+    decode_bencoded_structure, decode_document, encode_bencoded_structure, Mode,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(value) = decode_bencoded_structure(data) else {
+        return;
+    };
+    let encoded = encode_bencoded_structure(&value);
+    let decoded = decode_bencoded_structure(&encoded).expect("re-encoded value must decode");
+    assert_eq!(decoded, value);
+    assert_eq!(encode_bencoded_structure(&decoded), encoded);
+
+    if decode_document(data, Mode::Strict).is_ok() {
+        assert_eq!(encoded, data);
+    }
+});
@@ -1,140 +1,840 @@
-/// Core Bencode decoder algorithm
+//! Core Bencode decoder and encoder algorithms, plus a serde data format built on them
 
-use base64::{engine::general_purpose, Engine as _};
-use serde_json::Value::Array;
-use serde_json::Value::Object;
-use serde_json::{Map, Value};
-use std::iter::Peekable;
-use std::vec::IntoIter;
+mod de;
+mod diff;
+mod json;
+mod pretty;
+mod query;
+mod ser;
+mod stream;
 
-pub fn decode_bencoded_structure(encoded_value: Vec<u8>) -> Result<Value, &'static str> {
-    let mut bytes = encoded_value.into_iter().peekable();
-    parse_bencoded_values(&mut bytes)
+pub use de::{from_bytes, from_reader, from_value};
+pub use diff::{diff, Change};
+pub use json::{BinaryFormat, JsonError};
+pub use pretty::{render_tree, summarize};
+pub use query::{parse_path, query, select, QueryError, Selector};
+pub use ser::{to_bytes, to_value, to_writer};
+pub use stream::{decode_prefix, Progress, StreamDecoder};
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io;
+use std::ops::Range;
+use thiserror::Error;
+
+/// A decoded bencode value. Byte strings are kept exactly as they appeared on the wire,
+/// and dictionaries keep their byte-string keys in sorted order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BValue {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<BValue>),
+    Dict(BTreeMap<Vec<u8>, BValue>),
 }
 
-fn parse_bencoded_values(bytes: &mut Peekable<IntoIter<u8>>) -> Result<Value, &'static str> {
-    let num_str: String = bytes
-        .clone()
-        .take_while(|c| (b'0'..=b'9').contains(&c))
-        .map(|c| c as char)
-        .collect();
+impl BValue {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            BValue::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            BValue::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
 
-    if !num_str.is_empty() {
-        if let Ok(num) = num_str.parse::<i64>() {
-            return parse_bencoded_string(bytes, num as usize);
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    pub fn as_dict(&self) -> Option<&BTreeMap<Vec<u8>, BValue>> {
+        match self {
+            BValue::Dict(d) => Some(d),
+            _ => None,
         }
     }
 
-    match bytes.peek() {
-        Some(&b'i') => parse_bencoded_number(bytes),
-        Some(&b'l') => parse_bencoded_list(bytes),
-        Some(&b'd') => parse_bencoded_map(bytes),
-        _ => Err("Unable to parse bencoded values"),
+    /// Looks up `key` if this value is a dictionary
+    pub fn get(&self, key: &str) -> Option<&BValue> {
+        self.as_dict().and_then(|d| d.get(key.as_bytes()))
     }
 }
 
-fn parse_bencoded_number(bytes: &mut Peekable<IntoIter<u8>>) -> Result<Value, &'static str> {
-    let mut number = String::new();
-    let mut is_float = false;
+/// A decoded bencode value that borrows its byte strings from the input buffer instead of
+/// copying them. Dictionary entries stay in the order they appeared on the wire.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BValueRef<'a> {
+    Int(i64),
+    Bytes(&'a [u8]),
+    List(Vec<BValueRef<'a>>),
+    Dict(Vec<(&'a [u8], BValueRef<'a>)>),
+}
 
-    bytes.next(); // i
+impl<'a> BValueRef<'a> {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            BValueRef::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
 
-    while let Some(c) = bytes.next() {
-        match c {
-            b'.' => {
-                is_float = true;
-                number.push(c as char);
-            }
-            b'e' => {
-                if is_float {
-                    if let Ok(float_val) = number.parse::<f64>() {
-                        return Ok(Value::Number(
-                            serde_json::Number::from_f64(float_val).unwrap(),
-                        ));
-                    }
-                } else {
-                    if let Ok(int_val) = number.parse::<i64>() {
-                        return Ok(Value::Number(serde_json::Number::from(int_val)));
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            BValueRef::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.as_bytes().and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    pub fn as_list(&self) -> Option<&[BValueRef<'a>]> {
+        match self {
+            BValueRef::List(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    /// Looks up `key` if this value is a dictionary. The last entry wins if a key is repeated.
+    pub fn get(&self, key: &str) -> Option<&BValueRef<'a>> {
+        match self {
+            BValueRef::Dict(d) => d
+                .iter()
+                .rev()
+                .find(|(k, _)| *k == key.as_bytes())
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Copies the value out of the input buffer
+    pub fn to_owned_value(&self) -> BValue {
+        match self {
+            BValueRef::Int(i) => BValue::Int(*i),
+            BValueRef::Bytes(b) => BValue::Bytes(b.to_vec()),
+            BValueRef::List(l) => BValue::List(l.iter().map(BValueRef::to_owned_value).collect()),
+            BValueRef::Dict(d) => BValue::Dict(
+                d.iter()
+                    .map(|(k, v)| (k.to_vec(), v.to_owned_value()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Errors raised while decoding bencode. Every variant carries the byte offset it occurred at
+/// and the path of the value being decoded, e.g. `info.files[3].length`.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DecodeError {
+    #[error("unexpected end of input at byte {offset}: expected {expected} (at {path})")]
+    UnexpectedEof {
+        offset: usize,
+        expected: &'static str,
+        path: String,
+    },
+    #[error("unexpected byte {found:?} at byte {offset}: expected {expected} (at {path})")]
+    UnexpectedByte {
+        offset: usize,
+        found: char,
+        expected: &'static str,
+        path: String,
+    },
+    #[error("invalid integer {text:?} at byte {offset}: expected {expected} (at {path})")]
+    InvalidInteger {
+        offset: usize,
+        text: String,
+        expected: &'static str,
+        path: String,
+    },
+    #[error("non-canonical bencode at byte {offset}: {issue} (at {path})")]
+    NonCanonical {
+        offset: usize,
+        issue: NonCanonical,
+        path: String,
+    },
+    #[error("nesting deeper than {limit} levels at byte {offset} (at {path})")]
+    DepthLimitExceeded {
+        offset: usize,
+        limit: usize,
+        path: String,
+    },
+    #[error("input of {size} bytes is over the {limit} byte limit")]
+    SizeLimitExceeded { size: usize, limit: usize },
+    #[error(
+        "string of {length} bytes at byte {offset} is over the {limit} byte limit (at {path})"
+    )]
+    StringLimitExceeded {
+        offset: usize,
+        length: usize,
+        limit: usize,
+        path: String,
+    },
+    #[error("more than {limit} values at byte {offset} (at {path})")]
+    ElementLimitExceeded {
+        offset: usize,
+        limit: usize,
+        path: String,
+    },
+}
+
+/// Errors from the serde data format, i.e. [`from_bytes`], [`to_bytes`] and friends
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Message(String),
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+/// Encodings that decode unambiguously but aren't the single canonical form BEP 3 requires
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum NonCanonical {
+    #[error("integer has leading zeros")]
+    LeadingZeroInteger,
+    #[error("integer is negative zero")]
+    NegativeZero,
+    #[error("string length has leading zeros")]
+    LeadingZeroLength,
+    #[error("dictionary key is out of order")]
+    UnsortedKey,
+    #[error("dictionary key is a duplicate")]
+    DuplicateKey,
+    #[error("{0} bytes of trailing data after the root value")]
+    TrailingData(usize),
+}
+
+/// How the decoder treats non-canonical encodings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Accept them, recording a [`Warning`] for each
+    #[default]
+    Lenient,
+    /// Reject the first one with [`DecodeError::NonCanonical`]
+    Strict,
+}
+
+/// A non-canonical encoding accepted in [`Mode::Lenient`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("byte {offset}: {issue} (at {path})")]
+pub struct Warning {
+    pub offset: usize,
+    pub issue: NonCanonical,
+    pub path: String,
+}
+
+/// Bounds on untrusted input, so a hostile tracker or peer can't exhaust our stack or memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Lists and dictionaries nested inside each other
+    pub max_depth: usize,
+    /// Length of the whole encoded input
+    pub max_size: usize,
+    /// Length of a single byte string
+    pub max_string_length: usize,
+    /// Integers, strings, lists and dictionaries in the whole input
+    pub max_elements: usize,
+}
+
+impl Default for Limits {
+    /// Generous enough for torrents with hundreds of thousands of pieces and files
+    fn default() -> Self {
+        Limits {
+            max_depth: 64,
+            max_size: 64 * 1024 * 1024,
+            max_string_length: 32 * 1024 * 1024,
+            max_elements: 4_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DecodeOptions {
+    pub mode: Mode,
+    pub limits: Limits,
+}
+
+/// Everything the decoder learned about a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    pub value: BValue,
+    /// Byte range of the top-level `info` dictionary, if there was one
+    pub info_span: Option<Range<usize>>,
+    pub warnings: Vec<Warning>,
+}
+
+pub fn decode_bencoded_structure(encoded_value: &[u8]) -> Result<BValue, DecodeError> {
+    decode_with_info_span(encoded_value).map(|(value, _)| value)
+}
+
+/// Decodes without copying: byte strings in the result point into `encoded_value`
+pub fn decode_borrowed(encoded_value: &[u8]) -> Result<BValueRef<'_>, DecodeError> {
+    Decoder::new(encoded_value, DecodeOptions::default()).parse_root()
+}
+
+/// Decodes a document and also returns the byte range that the top-level `info` dictionary
+/// occupied in `encoded_value`, so the info hash can be taken over the original bytes.
+pub fn decode_with_info_span(
+    encoded_value: &[u8],
+) -> Result<(BValue, Option<Range<usize>>), DecodeError> {
+    decode_document(encoded_value, Mode::Lenient).map(|d| (d.value, d.info_span))
+}
+
+/// Decodes a whole document, checking it for canonical encoding according to `mode`
+pub fn decode_document(encoded_value: &[u8], mode: Mode) -> Result<Decoded, DecodeError> {
+    decode_with_options(
+        encoded_value,
+        DecodeOptions {
+            mode,
+            ..DecodeOptions::default()
+        },
+    )
+}
+
+pub fn decode_with_options(
+    encoded_value: &[u8],
+    options: DecodeOptions,
+) -> Result<Decoded, DecodeError> {
+    let mut decoder = Decoder::new(encoded_value, options);
+    let value = decoder.parse_root()?.to_owned_value();
+    if decoder.pos < encoded_value.len() {
+        let trailing = encoded_value.len() - decoder.pos;
+        decoder.non_canonical(decoder.pos, NonCanonical::TrailingData(trailing))?;
+    }
+    Ok(Decoded {
+        value,
+        info_span: decoder.info_span,
+        warnings: decoder.warnings,
+    })
+}
+
+enum PathSegment<'a> {
+    Key(&'a [u8]),
+    Index(usize),
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+    path: Vec<PathSegment<'a>>,
+    info_span: Option<Range<usize>>,
+    mode: Mode,
+    warnings: Vec<Warning>,
+    limits: Limits,
+    depth: usize,
+    elements: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(input: &'a [u8], options: DecodeOptions) -> Self {
+        Decoder {
+            input,
+            pos: 0,
+            path: Vec::new(),
+            info_span: None,
+            mode: options.mode,
+            warnings: Vec::new(),
+            limits: options.limits,
+            depth: 0,
+            elements: 0,
+        }
+    }
+
+    fn parse_root(&mut self) -> Result<BValueRef<'a>, DecodeError> {
+        if self.input.len() > self.limits.max_size {
+            return Err(DecodeError::SizeLimitExceeded {
+                size: self.input.len(),
+                limit: self.limits.max_size,
+            });
+        }
+        self.parse_bencoded_values()
+    }
+
+    /// Called on entering a list or dictionary
+    fn descend(&mut self) -> Result<(), DecodeError> {
+        self.depth += 1;
+        if self.depth > self.limits.max_depth {
+            return Err(DecodeError::DepthLimitExceeded {
+                offset: self.pos,
+                limit: self.limits.max_depth,
+                path: self.path(),
+            });
+        }
+        Ok(())
+    }
+
+    fn path(&self) -> String {
+        if self.path.is_empty() {
+            return "<root>".to_string();
+        }
+        let mut path = String::new();
+        for segment in &self.path {
+            match segment {
+                PathSegment::Key(key) => {
+                    if !path.is_empty() {
+                        path.push('.');
                     }
+                    path.push_str(&String::from_utf8_lossy(key));
                 }
-                return Err("Invalid number");
+                PathSegment::Index(i) => path.push_str(&format!("[{i}]")),
             }
-            _ => number.push(c as char),
         }
+        path
     }
-    Err("Unclosed number")
-}
 
-fn parse_bencoded_string(
-    bytes: &mut Peekable<IntoIter<u8>>,
-    length: usize,
-) -> Result<Value, &'static str> {
-    while let Some(c) = bytes.next() {
-        match c {
-            b':' => {
-                let data: Vec<u8> = bytes.take(length).collect();
-                return if let Ok(utf8_string) = std::str::from_utf8(&data) {
-                    Ok(Value::String(utf8_string.parse().unwrap()))
-                } else {
-                    Ok(Value::String(general_purpose::STANDARD.encode(&data)))
-                };
+    fn unexpected(&self, offset: usize, expected: &'static str) -> DecodeError {
+        match self.input.get(offset) {
+            Some(&found) => DecodeError::UnexpectedByte {
+                offset,
+                found: found as char,
+                expected,
+                path: self.path(),
+            },
+            None => DecodeError::UnexpectedEof {
+                offset,
+                expected,
+                path: self.path(),
+            },
+        }
+    }
+
+    fn invalid_integer(&self, offset: usize, text: &[u8], expected: &'static str) -> DecodeError {
+        DecodeError::InvalidInteger {
+            offset,
+            text: String::from_utf8_lossy(text).into_owned(),
+            expected,
+            path: self.path(),
+        }
+    }
+
+    /// Rejects the encoding in strict mode, otherwise records a warning and carries on
+    fn non_canonical(&mut self, offset: usize, issue: NonCanonical) -> Result<(), DecodeError> {
+        match self.mode {
+            Mode::Strict => Err(DecodeError::NonCanonical {
+                offset,
+                issue,
+                path: self.path(),
+            }),
+            Mode::Lenient => {
+                self.warnings.push(Warning {
+                    offset,
+                    issue,
+                    path: self.path(),
+                });
+                Ok(())
             }
-            _ => {
-                continue;
+        }
+    }
+
+    fn parse_bencoded_values(&mut self) -> Result<BValueRef<'a>, DecodeError> {
+        self.elements += 1;
+        if self.elements > self.limits.max_elements {
+            return Err(DecodeError::ElementLimitExceeded {
+                offset: self.pos,
+                limit: self.limits.max_elements,
+                path: self.path(),
+            });
+        }
+        match self.input.get(self.pos) {
+            Some(b'0'..=b'9') => self.parse_bencoded_string().map(BValueRef::Bytes),
+            Some(b'i') => self.parse_bencoded_number(),
+            Some(b'l') => self.parse_bencoded_list(),
+            Some(b'd') => self.parse_bencoded_map(),
+            _ => Err(self.unexpected(self.pos, "a string length, 'i', 'l' or 'd'")),
+        }
+    }
+
+    /// Returns the bytes up to (not including) the next `delimiter`, moving past the delimiter
+    fn take_until(
+        &mut self,
+        delimiter: u8,
+        expected: &'static str,
+    ) -> Result<&'a [u8], DecodeError> {
+        let rest = &self.input[self.pos..];
+        match rest.iter().position(|&c| c == delimiter) {
+            Some(end) => {
+                self.pos += end + 1;
+                Ok(&rest[..end])
             }
+            None => Err(self.unexpected(self.input.len(), expected)),
         }
     }
-    Err("Bad string")
-}
 
-fn parse_bencoded_list(bytes: &mut Peekable<IntoIter<u8>>) -> Result<Value, &'static str> {
-    let mut list = Vec::new();
+    fn parse_bencoded_number(&mut self) -> Result<BValueRef<'a>, DecodeError> {
+        self.pos += 1; // i
+        let start = self.pos;
+        let digits = self.take_until(b'e', "'e' to close the integer")?;
+        let magnitude = digits.strip_prefix(b"-").unwrap_or(digits);
+        let value = std::str::from_utf8(digits)
+            .ok()
+            .filter(|_| !magnitude.is_empty() && magnitude.iter().all(u8::is_ascii_digit))
+            .and_then(|n| n.parse::<i64>().ok())
+            .ok_or_else(|| self.invalid_integer(start, digits, "a 64-bit integer"))?;
+        if digits == b"-0" {
+            self.non_canonical(start, NonCanonical::NegativeZero)?;
+        } else if magnitude.len() > 1 && magnitude[0] == b'0' {
+            self.non_canonical(start, NonCanonical::LeadingZeroInteger)?;
+        }
+        Ok(BValueRef::Int(value))
+    }
+
+    fn parse_bencoded_string(&mut self) -> Result<&'a [u8], DecodeError> {
+        let start = self.pos;
+        let digits = self.take_until(b':', "':' after the string length")?;
+        let length = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|n| n.parse::<usize>().ok())
+            .ok_or_else(|| self.invalid_integer(start, digits, "a string length"))?;
+        if length > self.limits.max_string_length {
+            return Err(DecodeError::StringLimitExceeded {
+                offset: start,
+                length,
+                limit: self.limits.max_string_length,
+                path: self.path(),
+            });
+        }
+        if digits.len() > 1 && digits[0] == b'0' {
+            self.non_canonical(start, NonCanonical::LeadingZeroLength)?;
+        }
+        let end = self
+            .pos
+            .checked_add(length)
+            .filter(|&end| end <= self.input.len())
+            .ok_or_else(|| self.unexpected(self.input.len(), "the rest of the string"))?;
+        let data = &self.input[self.pos..end];
+        self.pos = end;
+        Ok(data)
+    }
 
-    bytes.next(); // l
+    fn parse_bencoded_list(&mut self) -> Result<BValueRef<'a>, DecodeError> {
+        let mut list = Vec::new();
 
-    while let Some(c) = bytes.peek() {
-        match c {
-            b'e' => {
-                bytes.next(); // e
-                return Ok(Array(list));
-            }
-            _ => {
-                list.push(parse_bencoded_values(bytes)?);
+        self.descend()?;
+        self.pos += 1; // l
+
+        while let Some(c) = self.input.get(self.pos) {
+            match c {
+                b'e' => {
+                    self.pos += 1; // e
+                    self.depth -= 1;
+                    return Ok(BValueRef::List(list));
+                }
+                _ => {
+                    self.path.push(PathSegment::Index(list.len()));
+                    list.push(self.parse_bencoded_values()?);
+                    self.path.pop();
+                }
             }
         }
+        Err(self.unexpected(self.pos, "'e' to close the list"))
     }
-    Err("Unclosed list")
-}
 
-fn parse_bencoded_map(bytes: &mut Peekable<IntoIter<u8>>) -> Result<Value, &'static str> {
-    let mut map: Map<String, Value> = Map::new();
+    fn parse_bencoded_map(&mut self) -> Result<BValueRef<'a>, DecodeError> {
+        let mut entries = Vec::new();
+        let mut previous_key: Option<&'a [u8]> = None;
 
-    bytes.next(); // d
+        self.descend()?;
+        self.pos += 1; // d
 
-    while let Some(c) = bytes.peek() {
-        match c {
-            b'e' => {
-                bytes.next(); // e
-                return Ok(Object(map));
-            }
-            _ => {
-                let hopeful_key = parse_bencoded_values(bytes);
-                match hopeful_key {
-                    Ok(value) => match value {
-                        Value::String(s) => {
-                            let value = parse_bencoded_values(bytes);
-                            map.insert(s, value.unwrap());
+        while let Some(c) = self.input.get(self.pos) {
+            match c {
+                b'e' => {
+                    self.pos += 1; // e
+                    self.depth -= 1;
+                    return Ok(BValueRef::Dict(entries));
+                }
+                b'0'..=b'9' => {
+                    let key_offset = self.pos;
+                    let key = self.parse_bencoded_string()?;
+                    match previous_key.map(|previous| key.cmp(previous)) {
+                        Some(Ordering::Equal) => {
+                            self.non_canonical(key_offset, NonCanonical::DuplicateKey)?
                         }
-                        _ => {
-                            return Err("Key wasn't a string!");
+                        Some(Ordering::Less) => {
+                            self.non_canonical(key_offset, NonCanonical::UnsortedKey)?
                         }
-                    },
-                    _ => {
-                        return Err("Key parsing error occurred");
+                        _ => {}
                     }
+                    previous_key = Some(key);
+                    self.path.push(PathSegment::Key(key));
+                    let start = self.pos;
+                    let value = self.parse_bencoded_values()?;
+                    if self.path.len() == 1 && key == b"info" {
+                        self.info_span = Some(start..self.pos);
+                    }
+                    self.path.pop();
+                    entries.push((key, value));
+                }
+                _ => {
+                    return Err(
+                        self.unexpected(self.pos, "a string key or 'e' to close the dictionary")
+                    );
                 }
             }
         }
+        Err(self.unexpected(self.pos, "'e' to close the dictionary"))
+    }
+}
+
+/// Encodes a value back to bencode. Dictionary keys are written in sorted order, so
+/// decoding canonical input and re-encoding it produces identical bytes.
+pub fn encode_bencoded_structure(value: &BValue) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_bencoded_value(value, &mut out);
+    out
+}
+
+fn write_bencoded_value(value: &BValue, out: &mut Vec<u8>) {
+    match value {
+        BValue::Int(i) => {
+            out.push(b'i');
+            out.extend_from_slice(i.to_string().as_bytes());
+            out.push(b'e');
+        }
+        BValue::Bytes(b) => write_bencoded_bytes(b, out),
+        BValue::List(l) => {
+            out.push(b'l');
+            for item in l {
+                write_bencoded_value(item, out);
+            }
+            out.push(b'e');
+        }
+        BValue::Dict(d) => {
+            out.push(b'd');
+            for (k, v) in d {
+                write_bencoded_bytes(k, out);
+                write_bencoded_value(v, out);
+            }
+            out.push(b'e');
+        }
+    }
+}
+
+fn write_bencoded_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(bytes.len().to_string().as_bytes());
+    out.push(b':');
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn arb_bvalue() -> impl Strategy<Value = BValue> {
+        let leaf = prop_oneof![
+            any::<i64>().prop_map(BValue::Int),
+            proptest::collection::vec(any::<u8>(), 0..32).prop_map(BValue::Bytes),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                proptest::collection::vec(inner.clone(), 0..8).prop_map(BValue::List),
+                proptest::collection::btree_map(
+                    proptest::collection::vec(any::<u8>(), 0..16),
+                    inner,
+                    0..8
+                )
+                .prop_map(BValue::Dict),
+            ]
+        })
+    }
+
+    #[test]
+    fn decodes_byte_strings_exactly() {
+        let decoded =
+            decode_bencoded_structure(b"d5:peers6:\x7f\0\0\x01\x1a\xe12:\xff\xfe4:spame").unwrap();
+        let peers = decoded.get("peers").unwrap();
+        assert_eq!(peers.as_bytes(), Some(&b"\x7f\0\0\x01\x1a\xe1"[..]));
+        assert_eq!(peers.as_str(), None);
+        // Keys needn't be UTF-8 either
+        let dict = decoded.as_dict().unwrap();
+        assert_eq!(dict[&b"\xff\xfe"[..]], BValue::Bytes(b"spam".to_vec()));
+        assert_eq!(decoded.get("spam"), None);
+    }
+
+    #[test]
+    fn encodes_dict_keys_sorted() {
+        let decoded = decode_bencoded_structure(b"d3:zzzi1e3:aaali2e4:spamee").unwrap();
+        assert_eq!(
+            encode_bencoded_structure(&decoded),
+            b"d3:aaali2e4:spame3:zzzi1ee".to_vec()
+        );
+    }
+
+    #[test]
+    fn errors_report_offset_and_path() {
+        let err = decode_bencoded_structure(b"d4:infod5:filesld6:lengthi1eed6:lengthi2xeeee")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            DecodeError::InvalidInteger {
+                offset: 39,
+                text: "2x".to_string(),
+                expected: "a 64-bit integer",
+                path: "info.files[1].length".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_non_canonical_encodings() {
+        for input in [
+            &b"i03e"[..],
+            b"i-0e",
+            b"02:ab",
+            b"d1:bi1e1:ai2ee",
+            b"d1:ai1e1:ai2ee",
+            b"i1ei2e",
+        ] {
+            assert!(decode_document(input, Mode::Strict).is_err());
+            let lenient = decode_document(input, Mode::Lenient).unwrap();
+            assert_eq!(lenient.warnings.len(), 1);
+        }
+        assert!(decode_bencoded_structure(b"i1.5e").is_err());
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct File {
+        length: u64,
+        path: Vec<String>,
+        #[serde(with = "serde_bytes")]
+        pieces: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        private: Option<bool>,
+        #[serde(rename = "piece length")]
+        piece_length: u32,
+    }
+
+    #[test]
+    fn serde_round_trips_structs() {
+        let file = File {
+            length: 3,
+            path: vec!["a".to_string(), "b.txt".to_string()],
+            pieces: vec![0xff, 0x00, 0x13],
+            private: None,
+            piece_length: 16384,
+        };
+        let bytes = to_bytes(&file).unwrap();
+        assert_eq!(
+            bytes,
+            b"d6:lengthi3e4:pathl1:a5:b.txte12:piece lengthi16384e6:pieces3:\xff\x00\x13e".to_vec()
+        );
+        assert_eq!(from_bytes::<File>(&bytes).unwrap(), file);
+    }
+
+    #[test]
+    fn serde_rejects_trailing_data() {
+        assert_eq!(from_bytes::<i64>(b"i1e").unwrap(), 1);
+        assert!(matches!(
+            from_bytes::<i64>(b"i1egarbage"),
+            Err(Error::Decode(DecodeError::NonCanonical {
+                offset: 3,
+                issue: NonCanonical::TrailingData(7),
+                ..
+            }))
+        ));
+        // Unsorted keys are common in the wild and still accepted
+        let file: File =
+            from_bytes(b"d4:pathl1:ae6:lengthi1e12:piece lengthi1e6:pieces0:e").unwrap();
+        assert_eq!(file.length, 1);
+    }
+
+    #[test]
+    fn serde_rejects_none_where_it_cant_be_left_out() {
+        #[derive(serde::Serialize)]
+        enum Wrapper {
+            Value(Option<i64>),
+        }
+        assert_eq!(to_bytes(&vec![Some(1), Some(3)]).unwrap(), b"li1ei3ee");
+        assert!(to_bytes(&vec![Some(1), None, Some(3)]).is_err());
+        assert!(to_bytes(&(1, ())).is_err());
+        assert_eq!(to_bytes(&Wrapper::Value(Some(2))).unwrap(), b"d5:Valuei2ee");
+        assert!(to_bytes(&Wrapper::Value(None)).is_err());
+        // Maps and structs just leave the entry out
+        let map = BTreeMap::from([("a", Some(1)), ("b", None)]);
+        assert_eq!(to_bytes(&map).unwrap(), b"d1:ai1ee");
+    }
+
+    #[test]
+    fn serde_present_values_are_always_some() {
+        let absent: File =
+            from_bytes(b"d6:lengthi0e4:pathle12:piece lengthi1e6:pieces0:e").unwrap();
+        assert_eq!(absent.private, None);
+        // Bencode has no null, so even a zero is `Some`
+        let zero: File =
+            from_bytes(b"d6:lengthi0e4:pathle12:piece lengthi1e6:pieces0:7:privatei0ee").unwrap();
+        assert_eq!(zero.private, Some(false));
+        assert_eq!(
+            from_bytes::<Option<String>>(b"0:").unwrap(),
+            Some(String::new())
+        );
+    }
+
+    #[test]
+    fn limits_reject_hostile_input() {
+        let limits = Limits {
+            max_depth: 3,
+            max_size: 32,
+            max_string_length: 8,
+            max_elements: 5,
+        };
+        let decode = |input: &[u8]| {
+            decode_with_options(
+                input,
+                DecodeOptions {
+                    limits,
+                    ..DecodeOptions::default()
+                },
+            )
+        };
+        assert!(matches!(
+            decode(b"llllee"),
+            Err(DecodeError::DepthLimitExceeded { offset: 3, .. })
+        ));
+        assert!(matches!(
+            decode(&[b'l'; 33]),
+            Err(DecodeError::SizeLimitExceeded { size: 33, .. })
+        ));
+        assert!(matches!(
+            decode(b"99999999999:"),
+            Err(DecodeError::StringLimitExceeded {
+                length: 99999999999,
+                ..
+            })
+        ));
+        assert!(matches!(
+            decode(b"li1ei2ei3ei4ei5ee"),
+            Err(DecodeError::ElementLimitExceeded { offset: 13, .. })
+        ));
+        assert!(decode_bencoded_structure(&[b'l'; 100_000]).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn decode_encode_is_byte_identical(value in arb_bvalue()) {
+            let canonical = encode_bencoded_structure(&value);
+            let decoded = decode_bencoded_structure(&canonical).unwrap();
+            prop_assert_eq!(&decoded, &value);
+            prop_assert_eq!(encode_bencoded_structure(&decoded), canonical);
+        }
     }
-    Err("Unclosed map")
 }
@@ -0,0 +1,279 @@
+//! Serde deserializer that walks a decoded [`BValue`] tree
+
+use super::{decode_document, BValue, DecodeError, Error, Mode, NonCanonical};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+use serde::forward_to_deserialize_any;
+use std::collections::btree_map;
+use std::io::Read;
+use std::vec;
+
+/// Deserializes a whole document. Bytes after the root value are an error; other
+/// non-canonical encodings, which real torrents and trackers produce, are accepted.
+pub fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let decoded = decode_document(bytes, Mode::Lenient)?;
+    let trailing = decoded
+        .warnings
+        .into_iter()
+        .find(|warning| matches!(warning.issue, NonCanonical::TrailingData(_)));
+    if let Some(warning) = trailing {
+        return Err(Error::Decode(DecodeError::NonCanonical {
+            offset: warning.offset,
+            issue: warning.issue,
+            path: warning.path,
+        }));
+    }
+    from_value(decoded.value)
+}
+
+pub fn from_reader<R: Read, T: DeserializeOwned>(mut reader: R) -> Result<T, Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    from_bytes(&bytes)
+}
+
+pub fn from_value<T: DeserializeOwned>(value: BValue) -> Result<T, Error> {
+    T::deserialize(ValueDeserializer(value))
+}
+
+fn unexpected(value: &BValue) -> de::Unexpected<'_> {
+    match value {
+        BValue::Int(i) => de::Unexpected::Signed(*i),
+        BValue::Bytes(b) => de::Unexpected::Bytes(b),
+        BValue::List(_) => de::Unexpected::Seq,
+        BValue::Dict(_) => de::Unexpected::Map,
+    }
+}
+
+struct ValueDeserializer(BValue);
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            BValue::Int(i) => visitor.visit_i64(i),
+            BValue::Bytes(b) => match String::from_utf8(b) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+            },
+            BValue::List(l) => visitor.visit_seq(SeqDeserializer(l.into_iter())),
+            BValue::Dict(d) => visitor.visit_map(MapDeserializer {
+                iter: d.into_iter(),
+                value: None,
+            }),
+        }
+    }
+
+    /// Bencode has no booleans, so flags such as `private` are integers
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            BValue::Int(i) => visitor.visit_bool(i != 0),
+            other => Err(de::Error::invalid_type(unexpected(&other), &visitor)),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            BValue::Bytes(b) => match String::from_utf8(b) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => Err(de::Error::invalid_value(
+                    de::Unexpected::Bytes(e.as_bytes()),
+                    &"a UTF-8 string",
+                )),
+            },
+            other => Err(de::Error::invalid_type(unexpected(&other), &visitor)),
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            BValue::Bytes(b) => visitor.visit_byte_buf(b),
+            other => Err(de::Error::invalid_type(unexpected(&other), &visitor)),
+        }
+    }
+
+    /// Bencode has no null, so a value that is present is always `Some`: `i0e` and `0:` included.
+    /// Absent fields become `None` through `#[serde(default)]`.
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            BValue::Bytes(_) => visitor.visit_enum(EnumDeserializer {
+                variant: self.0,
+                value: None,
+            }),
+            BValue::Dict(d) => {
+                let mut entries = d.into_iter();
+                match (entries.next(), entries.next()) {
+                    (Some((variant, value)), None) => visitor.visit_enum(EnumDeserializer {
+                        variant: BValue::Bytes(variant),
+                        value: Some(value),
+                    }),
+                    _ => Err(de::Error::invalid_value(
+                        de::Unexpected::Map,
+                        &"a dictionary with a single key",
+                    )),
+                }
+            }
+            other => Err(de::Error::invalid_type(unexpected(&other), &visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char seq tuple tuple_struct map struct
+    }
+}
+
+struct SeqDeserializer(vec::IntoIter<BValue>);
+
+impl<'de> SeqAccess<'de> for SeqDeserializer {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        self.0
+            .next()
+            .map(|value| seed.deserialize(ValueDeserializer(value)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct MapDeserializer {
+    iter: btree_map::IntoIter<Vec<u8>, BValue>,
+    value: Option<BValue>,
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(ValueDeserializer(BValue::Bytes(key)))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| Error::Message("map value requested before its key".to_string()))?;
+        seed.deserialize(ValueDeserializer(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// A unit variant is a bare string, any other variant a single-key dictionary
+struct EnumDeserializer {
+    variant: BValue,
+    value: Option<BValue>,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Error> {
+        let variant = seed.deserialize(ValueDeserializer(self.variant))?;
+        Ok((variant, VariantDeserializer(self.value)))
+    }
+}
+
+struct VariantDeserializer(Option<BValue>);
+
+impl VariantDeserializer {
+    fn contents(self) -> Result<ValueDeserializer, Error> {
+        self.0.map(ValueDeserializer).ok_or_else(|| {
+            de::Error::invalid_type(de::Unexpected::UnitVariant, &"variant contents")
+        })
+    }
+}
+
+impl<'de> VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.contents()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self.contents()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self.contents()?, visitor)
+    }
+}
@@ -0,0 +1,131 @@
+//! Structural comparison of two decoded trees
+
+use super::pretty::summarize;
+use super::BValue;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added {
+        path: String,
+        value: BValue,
+    },
+    Removed {
+        path: String,
+        value: BValue,
+    },
+    Changed {
+        path: String,
+        old: BValue,
+        new: BValue,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { path, value } => {
+                write!(f, "+ {path}: {}", summarize(value, last_key(path)))
+            }
+            Change::Removed { path, value } => {
+                write!(f, "- {path}: {}", summarize(value, last_key(path)))
+            }
+            Change::Changed { path, old, new } => write!(
+                f,
+                "~ {path}: {} -> {}",
+                summarize(old, last_key(path)),
+                summarize(new, last_key(path))
+            ),
+        }
+    }
+}
+
+fn last_key(path: &str) -> Option<&[u8]> {
+    path.rsplit('.').next().map(str::as_bytes)
+}
+
+/// Lists the added, removed and changed keys and list items between `old` and `new`, in tree
+/// order. Dictionaries and lists are compared member by member; anything else is either equal
+/// or changed as a whole.
+pub fn diff(old: &BValue, new: &BValue) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_into(old, new, "", &mut changes);
+    changes
+}
+
+fn join(path: &str, key: &[u8]) -> String {
+    let key = String::from_utf8_lossy(key);
+    if path.is_empty() {
+        key.into_owned()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn diff_into(old: &BValue, new: &BValue, path: &str, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (BValue::Dict(old), BValue::Dict(new)) => {
+            for (key, old_value) in old {
+                match new.get(key) {
+                    Some(new_value) => diff_into(old_value, new_value, &join(path, key), changes),
+                    None => changes.push(Change::Removed {
+                        path: join(path, key),
+                        value: old_value.clone(),
+                    }),
+                }
+            }
+            for (key, new_value) in new {
+                if !old.contains_key(key) {
+                    changes.push(Change::Added {
+                        path: join(path, key),
+                        value: new_value.clone(),
+                    });
+                }
+            }
+        }
+        (BValue::List(old), BValue::List(new)) => {
+            for i in 0..old.len().max(new.len()) {
+                let item_path = format!("{path}[{i}]");
+                match (old.get(i), new.get(i)) {
+                    (Some(o), Some(n)) => diff_into(o, n, &item_path, changes),
+                    (Some(o), None) => changes.push(Change::Removed {
+                        path: item_path,
+                        value: o.clone(),
+                    }),
+                    (None, Some(n)) => changes.push(Change::Added {
+                        path: item_path,
+                        value: n.clone(),
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        _ if old != new => changes.push(Change::Changed {
+            path: if path.is_empty() {
+                "<root>".to_string()
+            } else {
+                path.to_string()
+            },
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::decode_bencoded_structure;
+
+    #[test]
+    fn reports_added_removed_and_changed_keys() {
+        let old = decode_bencoded_structure(b"d1:ai1e1:bl1:x1:ye1:cd1:di1eee").unwrap();
+        let new = decode_bencoded_structure(b"d1:ai2e1:bl1:xe1:cd1:di1e1:ei5eee").unwrap();
+        let changes: Vec<String> = diff(&old, &new).iter().map(Change::to_string).collect();
+        assert_eq!(
+            changes,
+            vec!["~ a: 1 -> 2", "- b[1]: \"y\" (1 bytes)", "+ c.e: 5",]
+        );
+    }
+}
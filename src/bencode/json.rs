@@ -0,0 +1,206 @@
+//! Conversion between decoded trees and JSON. Text stays plain JSON strings; binary strings
+//! become tagged objects (`{"$hex": "..."}` or `{"$base64": "..."}`) and binary dictionary keys
+//! become `"$hex:..."` or `"$base64:..."`. Text keys that start with `$` get a second `$` so
+//! they can't be mistaken for tags, and the JSON converts back to the exact same bencode.
+
+use super::BValue;
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+const HEX_TAG: &str = "$hex";
+const BASE64_TAG: &str = "$base64";
+/// Starts every tag; a text key starting with it is written with it doubled
+const TAG_PREFIX: &str = "$";
+
+/// How byte strings that aren't text are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinaryFormat {
+    Hex,
+    #[default]
+    Base64,
+}
+
+impl BinaryFormat {
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            BinaryFormat::Hex => hex::encode(bytes),
+            BinaryFormat::Base64 => general_purpose::STANDARD.encode(bytes),
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            BinaryFormat::Hex => HEX_TAG,
+            BinaryFormat::Base64 => BASE64_TAG,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum JsonError {
+    #[error("{kind} at {path} has no bencode equivalent")]
+    Unsupported { kind: &'static str, path: String },
+    #[error("invalid {tag} string at {path}")]
+    BadEscape { tag: &'static str, path: String },
+}
+
+/// Decodes `"$hex:..."`/`"$base64:..."` escapes, or `None` if `text` isn't escaped
+fn unescape(text: &str, path: &str) -> Option<Result<Vec<u8>, JsonError>> {
+    let bad_escape = |tag| JsonError::BadEscape {
+        tag,
+        path: path.to_string(),
+    };
+    if let Some(encoded) = text.strip_prefix(HEX_TAG).and_then(|t| t.strip_prefix(':')) {
+        return Some(hex::decode(encoded).map_err(|_| bad_escape(HEX_TAG)));
+    }
+    if let Some(encoded) = text
+        .strip_prefix(BASE64_TAG)
+        .and_then(|t| t.strip_prefix(':'))
+    {
+        return Some(
+            general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|_| bad_escape(BASE64_TAG)),
+        );
+    }
+    None
+}
+
+impl BValue {
+    /// Converts to JSON for display, tagging binary strings as base64
+    pub fn to_json(&self) -> Value {
+        self.to_json_with(BinaryFormat::Base64)
+    }
+
+    /// Converts to JSON, tagging byte strings that aren't valid UTF-8 in `binary` format
+    pub fn to_json_with(&self, binary: BinaryFormat) -> Value {
+        match self {
+            BValue::Int(i) => Value::Number((*i).into()),
+            BValue::Bytes(b) => match std::str::from_utf8(b) {
+                Ok(s) => Value::String(s.to_string()),
+                Err(_) => {
+                    let mut tagged = Map::new();
+                    tagged.insert(binary.tag().to_string(), Value::String(binary.encode(b)));
+                    Value::Object(tagged)
+                }
+            },
+            BValue::List(l) => Value::Array(l.iter().map(|v| v.to_json_with(binary)).collect()),
+            BValue::Dict(d) => {
+                let mut map = Map::new();
+                for (k, v) in d {
+                    let key = match std::str::from_utf8(k) {
+                        Ok(key) if key.starts_with(TAG_PREFIX) => format!("{TAG_PREFIX}{key}"),
+                        Ok(key) => key.to_string(),
+                        Err(_) => format!("{}:{}", binary.tag(), binary.encode(k)),
+                    };
+                    map.insert(key, v.to_json_with(binary));
+                }
+                Value::Object(map)
+            }
+        }
+    }
+
+    /// The inverse of [`BValue::to_json_with`], accepting either binary tag
+    pub fn from_json(json: &Value) -> Result<BValue, JsonError> {
+        from_json_at(json, "<root>")
+    }
+}
+
+fn from_json_at(json: &Value, path: &str) -> Result<BValue, JsonError> {
+    let unsupported = |kind| {
+        Err(JsonError::Unsupported {
+            kind,
+            path: path.to_string(),
+        })
+    };
+    match json {
+        Value::Null => unsupported("null"),
+        Value::Bool(b) => Ok(BValue::Int(*b as i64)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Ok(BValue::Int(i)),
+            None => unsupported("a non-integer or out of range number"),
+        },
+        Value::String(s) => Ok(BValue::Bytes(s.as_bytes().to_vec())),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| from_json_at(item, &format!("{path}[{i}]")))
+            .collect::<Result<_, _>>()
+            .map(BValue::List),
+        Value::Object(map) => {
+            if map.len() == 1 {
+                if let Some((tag, Value::String(encoded))) = map.iter().next() {
+                    if let Some(bytes) = unescape(&format!("{tag}:{encoded}"), path) {
+                        return bytes.map(BValue::Bytes);
+                    }
+                }
+            }
+            let mut dict = BTreeMap::new();
+            for (key, value) in map {
+                let child = if path == "<root>" {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                let key = match key.strip_prefix(TAG_PREFIX) {
+                    Some(text) if text.starts_with(TAG_PREFIX) => text.as_bytes().to_vec(),
+                    _ => match unescape(key, &child) {
+                        Some(bytes) => bytes?,
+                        None => key.as_bytes().to_vec(),
+                    },
+                };
+                dict.insert(key, from_json_at(value, &child)?);
+            }
+            Ok(BValue::Dict(dict))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::{decode_bencoded_structure, encode_bencoded_structure};
+
+    #[test]
+    fn json_round_trips_binary_values_and_keys() {
+        let original = b"d5:filesd20:\xaa\xbb\xcc\xdd\xee\xff\x00\x11\x22\x33\x44\x55\x66\x77\x88\x99\xaa\xbb\xcc\xddd8:completei1eee4:name4:text6:pieces2:\xff\x00e";
+        let value = decode_bencoded_structure(original).unwrap();
+        for binary in [BinaryFormat::Hex, BinaryFormat::Base64] {
+            let json = value.to_json_with(binary);
+            let text = serde_json::to_string(&json).unwrap();
+            let parsed: Value = serde_json::from_str(&text).unwrap();
+            let back = BValue::from_json(&parsed).unwrap();
+            assert_eq!(encode_bencoded_structure(&back), original.to_vec());
+        }
+        assert_eq!(
+            value.to_json_with(BinaryFormat::Hex)["pieces"],
+            serde_json::json!({"$hex": "ff00"})
+        );
+        assert!(BValue::from_json(&serde_json::json!({"a": 1.5})).is_err());
+    }
+
+    #[test]
+    fn json_round_trips_text_that_looks_like_tags() {
+        let round_trip = |original: &[u8]| {
+            let value = decode_bencoded_structure(original).unwrap();
+            for binary in [BinaryFormat::Hex, BinaryFormat::Base64] {
+                let back = BValue::from_json(&value.to_json_with(binary)).unwrap();
+                assert_eq!(encode_bencoded_structure(&back), original.to_vec());
+            }
+            value.to_json()
+        };
+        // A text key shaped like an escaped binary key
+        assert_eq!(
+            round_trip(b"d7:$hex:ab1:xe"),
+            serde_json::json!({"$$hex:ab": "x"})
+        );
+        // A text dictionary shaped like a tagged binary string
+        assert_eq!(
+            round_trip(b"d4:$hex2:ffe"),
+            serde_json::json!({"$$hex": "ff"})
+        );
+        round_trip(b"d2:$$i1e7:$base644:AAAAe");
+    }
+}
@@ -0,0 +1,113 @@
+//! Human-readable rendering of decoded trees: an indented tree view and one-line summaries
+
+use super::BValue;
+
+/// Text longer than this is cut short in summaries
+const MAX_TEXT: usize = 64;
+/// Binary strings show this many leading bytes as hex
+const MAX_BINARY_PREVIEW: usize = 16;
+
+/// One-line description of a value. `key` is the dictionary key it sits under, which lets
+/// `pieces` be shown as a count of SHA-1 hashes instead of a wall of binary.
+pub fn summarize(value: &BValue, key: Option<&[u8]>) -> String {
+    match value {
+        BValue::Int(i) => i.to_string(),
+        BValue::Bytes(b) if key == Some(b"pieces") && b.len() % 20 == 0 => {
+            format!("{} SHA-1 hashes ({} bytes)", b.len() / 20, b.len())
+        }
+        BValue::Bytes(b) => match std::str::from_utf8(b) {
+            Ok(text) if text.chars().count() > MAX_TEXT => {
+                let shown: String = text.chars().take(MAX_TEXT).collect();
+                format!("{:?}… ({} bytes)", shown, b.len())
+            }
+            Ok(text) => format!("{:?} ({} bytes)", text, b.len()),
+            Err(_) if b.len() > MAX_BINARY_PREVIEW => format!(
+                "<binary {}…> ({} bytes)",
+                hex::encode(&b[..MAX_BINARY_PREVIEW]),
+                b.len()
+            ),
+            Err(_) => format!("<binary {}> ({} bytes)", hex::encode(b), b.len()),
+        },
+        BValue::List(l) => format!("list ({} items)", l.len()),
+        BValue::Dict(d) => format!("dict ({} keys)", d.len()),
+    }
+}
+
+/// Renders `value` as an indented tree, one value per line
+pub fn render_tree(value: &BValue) -> String {
+    let mut out = String::new();
+    write_tree(value, None, 0, &mut out);
+    out
+}
+
+fn write_tree(value: &BValue, key: Option<&[u8]>, indent: usize, out: &mut String) {
+    out.push_str(&summarize(value, key));
+    out.push('\n');
+    match value {
+        BValue::List(l) => {
+            for (i, item) in l.iter().enumerate() {
+                out.push_str(&format!("{:indent$}[{i}]: ", "", indent = indent + 2));
+                write_tree(item, None, indent + 2, out);
+            }
+        }
+        BValue::Dict(d) => {
+            for (k, v) in d {
+                let name = String::from_utf8_lossy(k);
+                out.push_str(&format!("{:indent$}{name}: ", "", indent = indent + 2));
+                write_tree(v, Some(k), indent + 2, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::decode_bencoded_structure;
+
+    #[test]
+    fn summarizes_each_kind_of_value() {
+        let bytes = |b: &[u8]| BValue::Bytes(b.to_vec());
+        assert_eq!(summarize(&BValue::Int(-3), None), "-3");
+        assert_eq!(summarize(&bytes(b"spam"), None), "\"spam\" (4 bytes)");
+        assert_eq!(
+            summarize(&bytes(&[0xff; 40]), Some(b"pieces")),
+            "2 SHA-1 hashes (40 bytes)"
+        );
+        // Not a whole number of hashes, so shown like any other binary string
+        assert_eq!(
+            summarize(&bytes(&[0xff; 3]), Some(b"pieces")),
+            "<binary ffffff> (3 bytes)"
+        );
+        assert_eq!(
+            summarize(&bytes(&[0xfe; 17]), None),
+            format!("<binary {}…> (17 bytes)", "fe".repeat(16))
+        );
+        let long = "é".repeat(70);
+        assert_eq!(
+            summarize(&bytes(long.as_bytes()), None),
+            format!("{:?}… (140 bytes)", "é".repeat(64))
+        );
+        assert_eq!(summarize(&BValue::List(vec![]), None), "list (0 items)");
+    }
+
+    #[test]
+    fn renders_nested_values_indented() {
+        let value = decode_bencoded_structure(
+            b"d4:infod4:name1:x6:pieces20:aaaaaaaaaaaaaaaaaaaae4:listli1eli2eeee",
+        )
+        .unwrap();
+        assert_eq!(
+            render_tree(&value),
+            "dict (2 keys)\n  \
+             info: dict (2 keys)\n    \
+             name: \"x\" (1 bytes)\n    \
+             pieces: 1 SHA-1 hashes (20 bytes)\n  \
+             list: list (2 items)\n    \
+             [0]: 1\n    \
+             [1]: list (1 items)\n      \
+             [0]: 2\n"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Path expressions such as `info.files[*].path` or `announce-list[0]`, resolved against a
+//! decoded tree
+
+use super::BValue;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// `name` or `["name with.dots"]`
+    Key(String),
+    /// `[3]`
+    Index(usize),
+    /// `[*]` or `*`: every list item or dictionary value
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum QueryError {
+    #[error("invalid path expression at character {position}: {reason}")]
+    Syntax {
+        position: usize,
+        reason: &'static str,
+    },
+    #[error("nothing matches `{0}`")]
+    NoMatch(String),
+}
+
+/// Parses a path expression. Keys are separated by `.` and may contain spaces, so
+/// `info.piece length` works; keys containing `.` or `[` can be written as `["key"]`.
+pub fn parse_path(expression: &str) -> Result<Vec<Selector>, QueryError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut selectors = Vec::new();
+    let mut pos = 0;
+    let syntax = |position, reason| Err(QueryError::Syntax { position, reason });
+
+    while pos < chars.len() {
+        match chars[pos] {
+            '.' if selectors.is_empty() || pos + 1 == chars.len() => {
+                return syntax(pos, "a path can't start or end with '.'");
+            }
+            '.' => pos += 1,
+            '[' => {
+                let close = match chars[pos..].iter().position(|&c| c == ']') {
+                    Some(offset) => pos + offset,
+                    None => return syntax(pos, "unclosed '['"),
+                };
+                let inner: String = chars[pos + 1..close].iter().collect();
+                let selector = if inner == "*" {
+                    Selector::Wildcard
+                } else if let Ok(index) = inner.parse::<usize>() {
+                    Selector::Index(index)
+                } else if inner.len() >= 2 && inner.starts_with('"') && inner.ends_with('"') {
+                    Selector::Key(inner[1..inner.len() - 1].to_string())
+                } else {
+                    return syntax(pos + 1, "expected an index, '*' or a quoted key");
+                };
+                selectors.push(selector);
+                pos = close + 1;
+                continue;
+            }
+            _ => {}
+        }
+        if chars.get(pos) == Some(&'.') {
+            return syntax(pos, "empty key");
+        }
+        let end = chars[pos..]
+            .iter()
+            .position(|&c| c == '.' || c == '[')
+            .map_or(chars.len(), |offset| pos + offset);
+        let key: String = chars[pos..end].iter().collect();
+        if key.is_empty() {
+            return syntax(pos, "empty key");
+        }
+        selectors.push(if key == "*" {
+            Selector::Wildcard
+        } else {
+            Selector::Key(key)
+        });
+        pos = end;
+    }
+    Ok(selectors)
+}
+
+/// Every value the selectors lead to, in tree order. Without wildcards there is at most one.
+pub fn select<'v>(root: &'v BValue, selectors: &[Selector]) -> Vec<&'v BValue> {
+    let mut current = vec![root];
+    for selector in selectors {
+        current = current
+            .into_iter()
+            .flat_map(|value| -> Vec<&'v BValue> {
+                match (selector, value) {
+                    (Selector::Key(key), BValue::Dict(d)) => {
+                        d.get(key.as_bytes()).into_iter().collect()
+                    }
+                    (Selector::Index(i), BValue::List(l)) => l.get(*i).into_iter().collect(),
+                    (Selector::Wildcard, BValue::List(l)) => l.iter().collect(),
+                    (Selector::Wildcard, BValue::Dict(d)) => d.values().collect(),
+                    _ => Vec::new(),
+                }
+            })
+            .collect();
+    }
+    current
+}
+
+/// Parses and resolves `expression`. Wildcard expressions yield a list of all matches.
+pub fn query(root: &BValue, expression: &str) -> Result<BValue, QueryError> {
+    let selectors = parse_path(expression)?;
+    let matches = select(root, &selectors);
+    if selectors.contains(&Selector::Wildcard) {
+        return Ok(BValue::List(matches.into_iter().cloned().collect()));
+    }
+    matches
+        .first()
+        .map(|&value| value.clone())
+        .ok_or_else(|| QueryError::NoMatch(expression.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::decode_bencoded_structure;
+
+    #[test]
+    fn resolves_keys_indices_and_wildcards() {
+        let torrent = decode_bencoded_structure(
+            b"d13:announce-listll1:ael1:bee4:infod5:filesld6:lengthi1e4:pathl1:xeed6:lengthi2e4:pathl1:y1:zeee12:piece lengthi16eee",
+        )
+        .unwrap();
+        let path = |p| query(&torrent, p).unwrap();
+        let bytes = |s: &str| BValue::Bytes(s.as_bytes().to_vec());
+
+        assert_eq!(path("announce-list[1][0]"), bytes("b"));
+        assert_eq!(path("info.piece length"), BValue::Int(16));
+        assert_eq!(path(r#"info["piece length"]"#), BValue::Int(16));
+        assert_eq!(
+            path("info.files[*].length"),
+            BValue::List(vec![BValue::Int(1), BValue::Int(2)])
+        );
+        assert_eq!(
+            path("info.files[*].path[*]"),
+            BValue::List(vec![bytes("x"), bytes("y"), bytes("z")])
+        );
+        assert_eq!(
+            query(&torrent, "info.files[5]"),
+            Err(QueryError::NoMatch("info.files[5]".to_string()))
+        );
+        assert!(parse_path("info..files").is_err());
+        assert!(parse_path("info.files[x]").is_err());
+    }
+}
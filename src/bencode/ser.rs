@@ -0,0 +1,533 @@
+//! Serde serializer that builds a [`BValue`] tree, which is then written out by the encoder
+
+use super::{encode_bencoded_structure, BValue, Error};
+use serde::ser::{self, Impossible, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Serializes `value` to bencode. Map and struct keys are sorted and `None` fields are omitted;
+/// a `None` anywhere else, such as in a list, is an error since leaving it out would change the
+/// value's shape.
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    Ok(encode_bencoded_structure(&to_value(value)?))
+}
+
+pub fn to_writer<W: Write, T: Serialize + ?Sized>(mut writer: W, value: &T) -> Result<(), Error> {
+    writer.write_all(&to_bytes(value)?)?;
+    Ok(())
+}
+
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<BValue, Error> {
+    value
+        .serialize(ValueSerializer)
+        .and_then(|v| v.ok_or_else(|| Error::Message("nothing to serialize".to_string())))
+}
+
+/// Serializes to `Some(value)`, or `None` for values bencode has no encoding for (`None`, `()`)
+/// which are skipped by the enclosing dictionary and rejected everywhere else.
+struct ValueSerializer;
+
+/// Serializes a value that can't be left out, such as a list element
+fn serialize_required<T: Serialize + ?Sized>(value: &T) -> Result<BValue, Error> {
+    value.serialize(ValueSerializer)?.ok_or_else(|| {
+        Error::Message("None and unit values can only be left out of dictionaries".to_string())
+    })
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Option<BValue>;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = VariantSerializer<SeqSerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = VariantSerializer<MapSerializer>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Error> {
+        Ok(Some(BValue::Int(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Error> {
+        i64::try_from(v)
+            .map_err(|_| Error::Message(format!("{v} doesn't fit in a bencode integer")))
+            .and_then(|v| self.serialize_i64(v))
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Error> {
+        Err(Error::Message(
+            "bencode has no floating point type".to_string(),
+        ))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Error> {
+        Err(Error::Message(
+            "bencode has no floating point type".to_string(),
+        ))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Error> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Error> {
+        Ok(Some(BValue::Bytes(v.to_vec())))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Error> {
+        let dict = BTreeMap::from([(variant.as_bytes().to_vec(), serialize_required(value)?)]);
+        Ok(Some(BValue::Dict(dict)))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Ok(SeqSerializer(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Ok(VariantSerializer {
+            variant,
+            inner: SeqSerializer(Vec::with_capacity(len)),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Ok(MapSerializer::default())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Ok(MapSerializer::default())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Ok(VariantSerializer {
+            variant,
+            inner: MapSerializer::default(),
+        })
+    }
+}
+
+struct SeqSerializer(Vec<BValue>);
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.0.push(serialize_required(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Option<BValue>, Error> {
+        Ok(Some(BValue::List(self.0)))
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Option<BValue>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Option<BValue>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Option<BValue>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+#[derive(Default)]
+struct MapSerializer {
+    dict: BTreeMap<Vec<u8>, BValue>,
+    next_key: Option<Vec<u8>>,
+}
+
+impl MapSerializer {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: Vec<u8>, value: &T) -> Result<(), Error> {
+        if let Some(value) = value.serialize(ValueSerializer)? {
+            self.dict.insert(key, value);
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Option<BValue>, Error> {
+        Ok(Some(BValue::Dict(self.dict)))
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Option<BValue>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.next_key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| Error::Message("map value serialized before its key".to_string()))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Option<BValue>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        self.finish()
+    }
+}
+
+/// Wraps tuple and struct variants as a single-key dictionary, `{variant: contents}`
+struct VariantSerializer<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> VariantSerializer<S> {
+    fn wrap(variant: &'static str, contents: Option<BValue>) -> Result<Option<BValue>, Error> {
+        let mut dict = BTreeMap::new();
+        if let Some(contents) = contents {
+            dict.insert(variant.as_bytes().to_vec(), contents);
+        }
+        Ok(Some(BValue::Dict(dict)))
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<SeqSerializer> {
+    type Ok = Option<BValue>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.inner.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        Self::wrap(self.variant, self.inner.finish()?)
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<MapSerializer> {
+    type Ok = Option<BValue>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.inner.insert(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Error> {
+        Self::wrap(self.variant, self.inner.finish()?)
+    }
+}
+
+/// Dictionary keys must be byte strings; anything else is rejected
+struct KeySerializer;
+
+impl KeySerializer {
+    fn not_a_string<T>() -> Result<T, Error> {
+        Err(Error::Message(
+            "dictionary keys must be strings".to_string(),
+        ))
+    }
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = Vec<u8>;
+    type Error = Error;
+    type SerializeSeq = Impossible<Vec<u8>, Error>;
+    type SerializeTuple = Impossible<Vec<u8>, Error>;
+    type SerializeTupleStruct = Impossible<Vec<u8>, Error>;
+    type SerializeTupleVariant = Impossible<Vec<u8>, Error>;
+    type SerializeMap = Impossible<Vec<u8>, Error>;
+    type SerializeStruct = Impossible<Vec<u8>, Error>;
+    type SerializeStructVariant = Impossible<Vec<u8>, Error>;
+
+    fn serialize_str(self, v: &str) -> Result<Vec<u8>, Error> {
+        Ok(v.as_bytes().to_vec())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(v.to_vec())
+    }
+
+    fn serialize_char(self, v: char) -> Result<Vec<u8>, Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Vec<u8>, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Vec<u8>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_none(self) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_unit(self) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Vec<u8>, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Self::not_a_string()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Self::not_a_string()
+    }
+}
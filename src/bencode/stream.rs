@@ -0,0 +1,319 @@
+//! Push-based decoder for values that arrive over a socket in chunks
+
+use super::{decode_with_options, BValue, DecodeError, DecodeOptions, Limits};
+
+/// Outcome of [`StreamDecoder::decode`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    /// The value isn't complete yet; at least this many more bytes are needed
+    NeedMore(usize),
+    /// A whole value was decoded from the first `consumed` bytes fed since the last value
+    Complete(BValue, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Expecting the start of a value, or `e` to close a list or dictionary
+    Value,
+    /// Inside `i...e`
+    Int,
+    /// Reading the decimal length prefix of a string
+    Length(usize),
+    /// Inside a string's bytes, with this many left
+    StringBody(usize),
+}
+
+/// A list or dictionary that has been opened but not closed yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Open {
+    List,
+    /// A dictionary whose next item is a key
+    DictKey,
+    /// A dictionary whose next item is the value of the key just read
+    DictValue,
+}
+
+/// Buffers fed bytes and scans them just far enough to find where the first value ends. The
+/// scan resumes where it stopped however the input is chunked and enforces every limit as it
+/// goes; once the end is found, that slice is decoded again in one pass to build the value.
+/// Whatever follows a complete value (e.g. the raw piece in a ut_metadata `data` message) is
+/// left in the buffer.
+#[derive(Debug, Clone)]
+pub struct StreamDecoder {
+    buffer: Vec<u8>,
+    scanned: usize,
+    open: Vec<Open>,
+    /// Values started so far, counted against `max_elements`; like the one-shot decoder,
+    /// dictionary keys aren't counted
+    elements: usize,
+    state: State,
+    limits: Limits,
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::with_limits(Limits::default())
+    }
+
+    /// Enforces `limits` while buffering, so an endless string or nesting is cut off early
+    pub fn with_limits(limits: Limits) -> Self {
+        StreamDecoder {
+            buffer: Vec::new(),
+            scanned: 0,
+            open: Vec::new(),
+            elements: 0,
+            state: State::Value,
+            limits,
+        }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Bytes fed but not yet part of a decoded value
+    pub fn remaining(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn take_remaining(&mut self) -> Vec<u8> {
+        self.reset();
+        std::mem::take(&mut self.buffer)
+    }
+
+    /// Tries to decode the next value from the bytes fed so far. On `Complete` the value's bytes
+    /// are dropped from the buffer and the decoder is ready for the next value.
+    pub fn decode(&mut self) -> Result<Progress, DecodeError> {
+        match self.scan() {
+            Ok(Some(end)) => {
+                let value = self.decode_slice(end)?;
+                self.buffer.drain(..end);
+                self.reset();
+                Ok(Progress::Complete(value, end))
+            }
+            Ok(None) if self.buffer.len() > self.limits.max_size => {
+                Err(DecodeError::SizeLimitExceeded {
+                    size: self.buffer.len(),
+                    limit: self.limits.max_size,
+                })
+            }
+            Ok(None) => Ok(Progress::NeedMore(self.bytes_needed())),
+            Err(bad_byte) => {
+                // Decoding up to the offending byte reports it with its offset and path
+                let err = self.decode_slice(bad_byte + 1).err();
+                self.reset();
+                Err(err.unwrap_or(DecodeError::UnexpectedByte {
+                    offset: bad_byte,
+                    found: self.buffer[bad_byte] as char,
+                    expected: "a bencoded value",
+                    path: "<root>".to_string(),
+                }))
+            }
+        }
+    }
+
+    fn decode_slice(&self, end: usize) -> Result<BValue, DecodeError> {
+        let options = DecodeOptions {
+            limits: self.limits,
+            ..DecodeOptions::default()
+        };
+        decode_with_options(&self.buffer[..end], options).map(|decoded| decoded.value)
+    }
+
+    fn reset(&mut self) {
+        self.scanned = 0;
+        self.open.clear();
+        self.elements = 0;
+        self.state = State::Value;
+    }
+
+    fn bytes_needed(&self) -> usize {
+        let current = match self.state {
+            State::StringBody(left) => left,
+            State::Int | State::Length(_) => 1,
+            State::Value if self.open.is_empty() => 1,
+            State::Value => 0,
+        };
+        current + self.open.len()
+    }
+
+    /// Advances over newly fed bytes. Returns the end offset of the first complete value, or
+    /// the offset of a byte that can't start or continue a value or that breaks a limit.
+    fn scan(&mut self) -> Result<Option<usize>, usize> {
+        while self.scanned < self.buffer.len() {
+            let pos = self.scanned;
+            let c = self.buffer[pos];
+            self.scanned += 1;
+            let at_key = self.open.last() == Some(&Open::DictKey);
+            if self.state == State::Value && c != b'e' && !at_key {
+                self.elements += 1;
+                if self.elements > self.limits.max_elements {
+                    return Err(pos);
+                }
+            }
+            let value_done = match self.state {
+                State::Value => match c {
+                    b'0'..=b'9' => {
+                        self.state = State::Length((c - b'0') as usize);
+                        false
+                    }
+                    b'i' => {
+                        self.state = State::Int;
+                        false
+                    }
+                    b'l' | b'd' if self.open.len() < self.limits.max_depth => {
+                        self.open.push(match c {
+                            b'l' => Open::List,
+                            _ => Open::DictKey,
+                        });
+                        false
+                    }
+                    b'e' if !self.open.is_empty() => {
+                        self.open.pop();
+                        true
+                    }
+                    _ => return Err(pos),
+                },
+                State::Int => match c {
+                    b'e' => {
+                        self.state = State::Value;
+                        true
+                    }
+                    b'-' | b'0'..=b'9' => false,
+                    _ => return Err(pos),
+                },
+                State::Length(length) => match c {
+                    b':' if length > self.limits.max_string_length => return Err(pos),
+                    b':' if length == 0 => {
+                        self.state = State::Value;
+                        true
+                    }
+                    b':' => {
+                        self.state = State::StringBody(length);
+                        false
+                    }
+                    b'0'..=b'9' => {
+                        let length = length
+                            .saturating_mul(10)
+                            .saturating_add((c - b'0') as usize);
+                        self.state = State::Length(length);
+                        false
+                    }
+                    _ => return Err(pos),
+                },
+                State::StringBody(left) => {
+                    let available = self.buffer.len() - pos;
+                    let taken = left.min(available);
+                    self.scanned = pos + taken;
+                    if taken == left {
+                        self.state = State::Value;
+                        true
+                    } else {
+                        self.state = State::StringBody(left - taken);
+                        false
+                    }
+                }
+            };
+            if value_done {
+                match self.open.last_mut() {
+                    None => return Ok(Some(self.scanned)),
+                    Some(open @ Open::DictKey) => *open = Open::DictValue,
+                    Some(open @ Open::DictValue) => *open = Open::DictKey,
+                    Some(Open::List) => {}
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Decodes the value at the start of `input`, leaving whatever follows it alone
+pub fn decode_prefix(input: &[u8]) -> Result<Progress, DecodeError> {
+    let mut decoder = StreamDecoder::new();
+    decoder.feed(input);
+    decoder.decode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_across_arbitrary_chunks() {
+        let message = b"d8:msg_typei1e5:piecei0e10:total_sizei5ee";
+        let mut decoder = StreamDecoder::new();
+        for chunk in message[..message.len() - 1].chunks(3) {
+            decoder.feed(chunk);
+            assert!(matches!(decoder.decode(), Ok(Progress::NeedMore(n)) if n >= 1));
+        }
+        decoder.feed(b"e");
+        let expected = crate::bencode::decode_bencoded_structure(message).unwrap();
+        assert_eq!(
+            decoder.decode(),
+            Ok(Progress::Complete(expected, message.len()))
+        );
+    }
+
+    #[test]
+    fn leaves_trailing_payload_in_the_buffer() {
+        let mut decoder = StreamDecoder::new();
+        decoder.feed(b"d8:msg_typei1ee");
+        decoder.feed(b"\x00\x01payload");
+        assert!(matches!(decoder.decode(), Ok(Progress::Complete(_, 15))));
+        assert_eq!(decoder.take_remaining(), b"\x00\x01payload".to_vec());
+        assert_eq!(decode_prefix(b"5:ab"), Ok(Progress::NeedMore(3)));
+    }
+
+    #[test]
+    fn enforces_limits_before_the_value_is_complete() {
+        let mut decoder = StreamDecoder::new();
+        decoder.feed(b"99999999999:");
+        assert!(matches!(
+            decoder.decode(),
+            Err(DecodeError::StringLimitExceeded { .. })
+        ));
+        let mut decoder = StreamDecoder::new();
+        decoder.feed(&[b'l'; 65]);
+        assert!(matches!(
+            decoder.decode(),
+            Err(DecodeError::DepthLimitExceeded { offset: 64, .. })
+        ));
+
+        let mut decoder = StreamDecoder::with_limits(Limits {
+            max_elements: 3,
+            ..Limits::default()
+        });
+        decoder.feed(b"li1ei2ei3");
+        assert!(matches!(
+            decoder.decode(),
+            Err(DecodeError::ElementLimitExceeded { offset: 7, .. })
+        ));
+
+        // Keys don't count, in one pass or streamed
+        let limits = Limits {
+            max_elements: 3,
+            ..Limits::default()
+        };
+        let options = DecodeOptions {
+            limits,
+            ..DecodeOptions::default()
+        };
+        let within = b"d1:ai1e1:bi2ee";
+        assert!(decode_with_options(within, options).is_ok());
+        let mut decoder = StreamDecoder::with_limits(limits);
+        decoder.feed(within);
+        assert!(matches!(decoder.decode(), Ok(Progress::Complete(_, 14))));
+        let mut decoder = StreamDecoder::with_limits(limits);
+        decoder.feed(b"d1:ai1e1:bi2e1:ci3ee");
+        assert!(matches!(
+            decoder.decode(),
+            Err(DecodeError::ElementLimitExceeded { offset: 16, .. })
+        ));
+    }
+}
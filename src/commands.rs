@@ -1,179 +1,401 @@
-/// Functions that carry out the execution of the client's CLI commands
-
-use crate::bencode::decode_bencoded_structure;
-use crate::torrent::{convert_byte_array_peers, hash_info, percent_encode_hex, print_hash_pieces,
-                     TorrentInfo, TrackerRequest};
-use base64::engine::general_purpose;
-use base64::Engine;
-use std::fs::{File};
+//! Functions that carry out the execution of the client's CLI commands
+
+use crate::bencode::{self, decode_bencoded_structure, decode_document, encode_bencoded_structure, diff, query, render_tree,
+                     BValue, BinaryFormat, Mode};
+use crate::magnet::MagnetLink;
+use crate::peer::download::{download_piece, DEFAULT_QUEUE_DEPTH};
+use crate::peer::handshake::{self, Handshake};
+use crate::peer::metadata::{extension_handshake, fetch_metadata};
+use crate::storage::{self, PieceStatus};
+use crate::torrent::create::{create, CreateOptions};
+use crate::torrent::edit::{self, TorrentEdit};
+use crate::torrent::{convert_byte_array_peers, encode_with_info, hash_info, percent_encode_hex, print_file_tree, print_hash_pieces,
+                     Metainfo, TrackerRequest, TrackerResponse};
+use std::collections::BTreeMap;
+use std::fs;
 use std::io::{Read, Write};
 use std::net::{SocketAddrV4, TcpStream};
+use std::path::Path;
+use std::time::Duration;
 use anyhow::{Result, anyhow};
 
-pub fn download_torrent_piece(file_name: String, output_file_name: String, piece: u32) -> Result<String> {
-    match fetch_torrent_peers(file_name.clone(), false) {
-        Ok(peer_array) => {
-            let peer = peer_array.get(0).cloned(); // Clone the first element
+/// The peer id we introduce ourselves with, to trackers and peers alike
+const PEER_ID: &[u8; 20] = b"00112233445566778899";
+/// How long to wait for a peer to connect, handshake or answer a message
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
 
-            match establish_peer_connection(file_name.clone(), peer.unwrap(), false) {
-                Ok((_peer_stream, peer_id)) => {
-                    println!("Completed handshake with peer: {peer_id} -- Going to get piece {piece} and write to {output_file_name}");
-                    Ok("Unfinished".to_string())
-                }
-                Err(e) => Err(anyhow!("Failed establishing connection to peer {}: {}", peer.unwrap(), e))
-            }
-        }
-        Err(e) => Err(anyhow!("Failed getting peer array: {}", e))
+/// Downloads piece `piece` from the first peer that has it, checks it against its hash and
+/// writes it to `output_file_name`. A magnet link's metadata is fetched from its peers first.
+pub fn download_torrent_piece(file_name: String, output_file_name: String, piece: u32, queue_depth: Option<usize>) -> Result<String> {
+    let metainfo = load_metainfo(&file_name)?;
+    if piece as usize >= metainfo.piece_count() {
+        return Err(anyhow!("The torrent has {} pieces, there's no piece {}", metainfo.piece_count(), piece));
     }
+    let peers = fetch_torrent_peers(file_name.clone(), false).map_err(|e| anyhow!("Failed getting peer array: {}", e))?;
+    let data = fetch_piece(&file_name, &metainfo, &peers, piece, queue_depth)?;
+    fs::write(&output_file_name, data).map_err(|e| anyhow!("Couldn't write {}: {}", output_file_name, e))?;
+    Ok(format!("Piece {} downloaded to {}.", piece, output_file_name))
 }
 
-pub fn establish_peer_connection(file_name: String, peer_address: SocketAddrV4, print: bool) -> Result<(TcpStream, String)> {
-    match fetch_torrent_info(file_name, false) {
-        Ok(torrent_info) => {
-            let mut handshake_message: Vec<u8> = Vec::new();
-            let raw_hashed_info = hex::decode(torrent_info.1).expect("Can't decode hashed info from hex");
-            let peer_id = "00112233445566778899";
-            let protocol_name = "BitTorrent protocol";
+/// Downloads the whole torrent into `output_dir`, piece by piece. Files are laid out by the
+/// storage layer, so pad files are never written and file attributes and symlinks are applied.
+pub fn download_torrent(file_name: String, output_dir: String, queue_depth: Option<usize>) -> Result<String> {
+    let metainfo = load_metainfo(&file_name)?;
+    if !metainfo.info().has_v1() {
+        return Err(anyhow!("Only torrents with v1 piece hashes can be downloaded"));
+    }
+    let peers = fetch_torrent_peers(file_name.clone(), false).map_err(|e| anyhow!("Failed getting peer array: {}", e))?;
+    let root = Path::new(&output_dir);
+    storage::prepare_files(&metainfo, root).map_err(|e| anyhow!("Couldn't create files in {}: {}", output_dir, e))?;
+    for piece in 0..metainfo.piece_count() {
+        let data = fetch_piece(&file_name, &metainfo, &peers, piece as u32, queue_depth)?;
+        storage::write_piece(&metainfo, root, piece, &data).map_err(|e| anyhow!("Couldn't write piece {}: {}", piece, e))?;
+    }
+    Ok(format!("Downloaded {} to {}.", metainfo.info().name, root.join(&metainfo.info().name).display()))
+}
 
-            handshake_message.push(protocol_name.len() as u8);
-            handshake_message.extend_from_slice(protocol_name.as_bytes());
-            handshake_message.extend(std::iter::repeat(0).take(8));
-            handshake_message.extend(raw_hashed_info);
-            handshake_message.extend_from_slice(peer_id.as_bytes());
+/// The torrent file, or for a magnet link the metadata fetched from its peers
+fn load_metainfo(source: &str) -> Result<Metainfo> {
+    match source.starts_with("magnet:") {
+        true => Ok(Metainfo::from_bytes(&fetch_magnet_torrent(source)?)?),
+        false => fetch_torrent_info(source.to_string(), false),
+    }
+}
 
+/// Downloads and checks one piece, trying `peers` in turn over a fresh connection each
+fn fetch_piece(source: &str, metainfo: &Metainfo, peers: &[SocketAddrV4], piece: u32, queue_depth: Option<usize>) -> Result<Vec<u8>> {
+    let hash = metainfo.piece_hashes().nth(piece as usize)
+        .ok_or_else(|| anyhow!("The torrent has {} pieces, there's no piece {}", metainfo.piece_count(), piece))?;
+    let length = metainfo.piece_size(piece as usize) as u32;
+    let queue_depth = queue_depth.unwrap_or(DEFAULT_QUEUE_DEPTH);
+    for &peer in peers {
+        let data = establish_peer_connection(source.to_string(), peer, false)
+            .and_then(|(mut stream, _)| Ok(download_piece(&mut stream, piece, length, hash, queue_depth)?));
+        match data {
+            Ok(data) => return Ok(data),
+            Err(e) => eprintln!("Couldn't get piece {} from {}: {}", piece, peer, e),
+        }
+    }
+    Err(anyhow!("No peer sent piece {}", piece))
+}
 
-            let mut stream = TcpStream::connect(peer_address)?;
-            let mut buffer = [0; 1024];
+/// What finding and greeting peers needs, taken from a torrent file or a magnet link
+struct Swarm {
+    tracker_url: Option<String>,
+    info_hashes: Vec<[u8; 20]>,
+    /// Bytes left to download, as reported to the tracker
+    left: u64,
+    /// Peers named by the source itself (`x.pe` in magnet links)
+    known_peers: Vec<SocketAddrV4>,
+}
 
-            stream.write_all(&handshake_message)?;
-            let bytes_read = stream.read(&mut buffer)?;
+fn load_swarm(source: &str) -> Result<Swarm> {
+    if source.starts_with("magnet:") {
+        let magnet: MagnetLink = source.parse()?;
+        Ok(Swarm {
+            tracker_url: magnet.trackers.first().cloned(),
+            info_hashes: magnet.swarm_hashes(),
+            // The size is unknown until the metadata is fetched, and trackers treat 0 as a seeder
+            left: 1,
+            known_peers: magnet.peers.iter().filter_map(|peer| peer.parse().ok()).collect(),
+        })
+    } else {
+        let metainfo = fetch_torrent_info(source.to_string(), false)?;
+        Ok(Swarm {
+            tracker_url: metainfo.tracker_url().map(str::to_string),
+            info_hashes: metainfo.swarm_hashes(),
+            left: metainfo.total_length(),
+            known_peers: Vec::new(),
+        })
+    }
+}
 
-            let (_, right) = buffer[..bytes_read].split_at(bytes_read - 20);
-            let hex_encoded_peer_id = hex::encode(right);
+pub fn establish_peer_connection(file_name: String, peer_address: SocketAddrV4, print: bool) -> Result<(TcpStream, Handshake)> {
+    match load_swarm(&file_name) {
+        Ok(swarm) => {
+            let ours = Handshake::new(swarm.info_hashes[0], *PEER_ID);
+            let (stream, theirs) = handshake::connect(peer_address.into(), &ours, PEER_TIMEOUT)?;
             if print {
-                println!("Peer ID: {}", hex_encoded_peer_id);
+                println!("Peer ID: {}", hex::encode(theirs.peer_id));
             }
-            Ok((stream, hex_encoded_peer_id))
+            Ok((stream, theirs))
         }
         Err(e) => Err(anyhow!("Error getting torrent info {}", e))
     }
 }
 
 pub fn fetch_torrent_peers(file_name: String, print: bool) -> Result<Vec<SocketAddrV4>> {
-    if let Ok(TorrentInfo(announce_url, hashed_info, length)) = fetch_torrent_info(file_name, false) {
-        let percent_encoded = percent_encode_hex(hashed_info);
-        let tracker_request = TrackerRequest {
-            info_hash: percent_encoded.to_string(),
-            peer_id: "00112233445566778899".to_string(),
-            port: 6881,
-            uploaded: 0,
-            downloaded: 0,
-            left: length as u32,
-            compact: 1,
-        };
-
-        let url_with_query = format!("{}?{}", announce_url, tracker_request.to_query_string());
-        let response = reqwest::blocking::get(url_with_query).expect("Query failed");
-        if response.status().is_success() {
-            let body_bytes = response
-                .bytes()
-                .expect("Couldn't convert to bytes")
-                .to_vec();
-            let response_decoded = decode_bencoded_structure(body_bytes);
-            match response_decoded {
-                Ok(value) => {
-                    let peers = value
-                        .as_object()
-                        .expect("Unable to convert value to object")
-                        .get("peers")
-                        .expect("Unable to get peers");
-                    let peers_string = peers.as_str().expect("Couldn't get peer string");
-                    let peers_string_decoded = general_purpose::STANDARD
-                        .decode(peers_string)
-                        .expect("Can't decode peers from hex");
-                    let peer_byte_array = peers_string_decoded.as_slice();
-                    let peer_array = convert_byte_array_peers(peer_byte_array);
-                    if print {
-                        for peer in &peer_array {
-                            println!("{peer}");
+    let swarm = load_swarm(&file_name)?;
+    let mut peer_array = swarm.known_peers;
+    match &swarm.tracker_url {
+        Some(announce_url) => {
+            // Hybrid torrents have a v1 and a v2 swarm, so announce to both and merge the peers;
+            // one swarm answering is enough
+            let mut last_error = None;
+            let mut announced = false;
+            for info_hash in swarm.info_hashes {
+                match announce(announce_url, info_hash, swarm.left) {
+                    Ok(peers) => {
+                        announced = true;
+                        for peer in peers {
+                            if !peer_array.contains(&peer) {
+                                peer_array.push(peer);
+                            }
                         }
                     }
-                    Ok(peer_array)
-                }
-                Err(e) => {
-                    Err(anyhow!("Couldn't decode response: {}", e))
+                    Err(e) => last_error = Some(e),
                 }
             }
-        } else {
-            Err(anyhow!("Bad response from client! {}", response.status()))
+            if let (false, Some(e)) = (announced, last_error) {
+                return Err(e);
+            }
         }
-    } else {
-        Err(anyhow!("Was unable to fetch torrent info"))
-    }
-}
-
-pub fn fetch_torrent_info(file_name: String, print: bool) -> Result<TorrentInfo> {
-    let mut bytes = Vec::new();
-    match File::open(file_name) {
-        Ok(mut file) => {
-            if let Ok(_) = file.read_to_end(&mut bytes) {
-                let decoded_file_result = decode_bencoded_structure(bytes);
-                match decoded_file_result {
-                    Ok(decoded_file) => {
-                        let file_obj = decoded_file
-                            .as_object()
-                            .expect("Unable to convert file to object");
-
-                        let announce = file_obj
-                            .get("announce")
-                            .expect("Announce not found in parsed file")
-                            .as_str()
-                            .expect("Announce is not a string");
-
-                        let info = file_obj
-                            .get("info")
-                            .expect("Info not found")
-                            .as_object()
-                            .expect("Info is not an object");
-
-                        let length = info
-                            .get("length")
-                            .expect("Length not found")
-                            .as_i64()
-                            .expect("Length is not an integer");
-
-                        let hashed_info = hash_info(info);
-
-                        if print {
-                            println!("Tracker URL: {}", announce);
-                            println!("Length: {}", length);
-                            println!("Info Hash: {}", hashed_info);
-                            print_hash_pieces(info);
-                        }
+        None if peer_array.is_empty() => return Err(anyhow!("Torrent has no tracker")),
+        None => {}
+    }
+    if print {
+        for peer in &peer_array {
+            println!("{peer}");
+        }
+    }
+    Ok(peer_array)
+}
 
-                        Ok(TorrentInfo(announce.to_string(), hashed_info, length))
-                    }
-                    Err(e) => Err(anyhow!("Wasn't able to decode the bencoded file: {}", e)),
-                }
-            } else {
-                Err(anyhow!("Failed to read file"))
+fn announce(announce_url: &str, info_hash: [u8; 20], left: u64) -> Result<Vec<SocketAddrV4>> {
+    let tracker_request = TrackerRequest {
+        info_hash: percent_encode_hex(hex::encode(info_hash)),
+        peer_id: String::from_utf8_lossy(PEER_ID).into_owned(),
+        port: 6881,
+        uploaded: 0,
+        downloaded: 0,
+        left,
+        compact: 1,
+    };
+
+    let url_with_query = format!("{}?{}", announce_url, tracker_request.to_query_string());
+    let response = reqwest::blocking::get(url_with_query).map_err(|e| anyhow!("Query failed: {}", e))?;
+    if response.status().is_success() {
+        let body_bytes = response.bytes().map_err(|e| anyhow!("Couldn't read the tracker response: {}", e))?.to_vec();
+        let response_decoded = bencode::from_bytes::<TrackerResponse>(&body_bytes);
+        match response_decoded {
+            Ok(TrackerResponse { failure_reason: Some(reason), .. }) => {
+                Err(anyhow!("Tracker refused the request: {}", reason))
+            }
+            Ok(tracker_response) => convert_byte_array_peers(&tracker_response.peers).map_err(|e| anyhow!("Bad tracker response: {}", e)),
+            Err(e) => {
+                Err(anyhow!("Couldn't decode response: {}", e))
             }
         }
-        Err(e) => {
-            Err(anyhow!("Error opening file: {}", e))
+    } else {
+        Err(anyhow!("Bad response from client! {}", response.status()))
+    }
+}
+
+pub fn fetch_torrent_info(file_name: String, print: bool) -> Result<Metainfo> {
+    let metainfo = Metainfo::from_file(&file_name)?;
+
+    if print {
+        if let Some(tracker_url) = metainfo.tracker_url() {
+            println!("Tracker URL: {}", tracker_url);
+        }
+        println!("Length: {}", metainfo.total_length());
+        println!("Version: {}", metainfo.version());
+        if metainfo.info().has_v1() {
+            println!("Info Hash: {}", metainfo.info_hash_hex());
+        }
+        if let Some(info_hash_v2) = metainfo.info_hash_v2_hex() {
+            println!("Info Hash v2: {}", info_hash_v2);
         }
+        println!("Magnet Link: {}", MagnetLink::from_metainfo(&metainfo));
+        if metainfo.is_multi_file() {
+            print_file_tree(&metainfo);
+        }
+        print_hash_pieces(metainfo.info());
     }
+    Ok(metainfo)
+}
+
+/// Fetches the info dictionary named by a magnet link from its peers, saves it as a
+/// `.torrent` (by default `<info hash>.torrent`) and prints it like `info`
+pub fn fetch_magnet_info(link: String, output_file: Option<String>) -> Result<()> {
+    let magnet: MagnetLink = link.parse()?;
+    let torrent = fetch_magnet_torrent(&link)?;
+    let swarm_hash = magnet.swarm_hash().ok_or_else(|| anyhow!("Magnet link has no info hash"))?;
+    let output_file = output_file.unwrap_or_else(|| format!("{}.torrent", hex::encode(swarm_hash)));
+    fs::write(&output_file, torrent).map_err(|e| anyhow!("Couldn't write {}: {}", output_file, e))?;
+    println!("Saved metadata to {}", output_file);
+    fetch_torrent_info(output_file, true)?;
+    Ok(())
 }
 
-pub fn print_bencoded_string(string: String) -> () {
-    let encoded_value_bytes = Vec::from(string.as_bytes());
-    let decoded_value = decode_bencoded_structure(encoded_value_bytes);
-    match decoded_value {
-        Ok(value) => {
-            println!("{}", value.to_string());
+/// Fetches the info dictionary a magnet link names from its peers and wraps it in a torrent
+/// carrying the link's trackers and web seeds
+fn fetch_magnet_torrent(link: &str) -> Result<Vec<u8>> {
+    let magnet: MagnetLink = link.parse()?;
+    let peers = fetch_torrent_peers(link.to_string(), false)?;
+    let mut info_bytes = None;
+    for peer in peers {
+        match fetch_metadata_from_peer(link, &magnet, peer) {
+            Ok(metadata) => {
+                info_bytes = Some(metadata);
+                break;
+            }
+            Err(e) => eprintln!("Couldn't get metadata from {}: {}", peer, e),
         }
-        Err(_) => {
-            eprintln!("Wasn't able to decode bencoded string")
+    }
+    let info_bytes = info_bytes.ok_or_else(|| anyhow!("No peer sent the metadata"))?;
+
+    let mut outer = BTreeMap::new();
+    if let Some(tracker) = magnet.trackers.first() {
+        outer.insert(b"announce".to_vec(), BValue::Bytes(tracker.as_bytes().to_vec()));
+    }
+    if magnet.trackers.len() > 1 {
+        let tiers = magnet.trackers.iter().map(|tracker| BValue::List(vec![BValue::Bytes(tracker.as_bytes().to_vec())]));
+        outer.insert(b"announce-list".to_vec(), BValue::List(tiers.collect()));
+    }
+    if !magnet.web_seeds.is_empty() {
+        let web_seeds = magnet.web_seeds.iter().map(|url| BValue::Bytes(url.as_bytes().to_vec()));
+        outer.insert(b"url-list".to_vec(), BValue::List(web_seeds.collect()));
+    }
+    Ok(encode_with_info(&outer, &info_bytes))
+}
+
+fn fetch_metadata_from_peer(link: &str, magnet: &MagnetLink, peer: SocketAddrV4) -> Result<Vec<u8>> {
+    let (mut stream, theirs) = establish_peer_connection(link.to_string(), peer, false)?;
+    if !theirs.supports_extension_protocol() {
+        return Err(anyhow!("peer doesn't support the extension protocol"));
+    }
+    let handshake = extension_handshake(&mut stream)?;
+    Ok(fetch_metadata(&mut stream, &handshake, |info_bytes| magnet.matches_metadata(info_bytes))?)
+}
+
+/// Creates a torrent for the file or directory `path` and writes it to `output_file`
+pub fn create_torrent_file(path: String, output_file: String, options: CreateOptions) -> Result<()> {
+    let metainfo = create(Path::new(&path), &options).map_err(|e| anyhow!("Couldn't create torrent: {}", e))?;
+    fs::write(&output_file, bencode::to_bytes(&metainfo)?)
+        .map_err(|e| anyhow!("Couldn't write {}: {}", output_file, e))?;
+    println!("Created {} ({} pieces of {} bytes)", output_file, metainfo.piece_count(), metainfo.info().piece_length);
+    println!("Info Hash: {}", metainfo.info_hash_hex());
+    Ok(())
+}
+
+/// Applies `edit` to a torrent file, writing the result to `output_file` or back in place
+pub fn edit_torrent_file(file_name: String, output_file: Option<String>, edit: TorrentEdit) -> Result<()> {
+    let bytes = fs::read(&file_name).map_err(|e| anyhow!("Error opening file: {}", e))?;
+    let edited = edit::apply(&bytes, &edit)?;
+    Metainfo::from_bytes(&edited.bytes).map_err(|e| anyhow!("Edited torrent would be invalid: {}", e))?;
+
+    let output_file = output_file.unwrap_or(file_name);
+    fs::write(&output_file, &edited.bytes).map_err(|e| anyhow!("Couldn't write {}: {}", output_file, e))?;
+    println!("Wrote {}", output_file);
+    if edited.info_changed {
+        eprintln!("Warning: the info dictionary changed, so the info hash is different and peers of the old torrent won't share this one");
+    }
+    println!("Info Hash: {}", hash_info(&edited.info_bytes));
+    Ok(())
+}
+
+/// Checks the payload at `path` against a torrent's piece hashes and reports good, bad and
+/// missing pieces per file. `path` may be the payload itself or the directory holding it.
+pub fn verify_payload(file_name: String, path: String, threads: Option<usize>, json: bool) -> Result<()> {
+    let metainfo = fetch_torrent_info(file_name, false)?;
+    let path = Path::new(&path);
+    let root = if !path.join(&metainfo.info().name).exists() && path.file_name() == Some(metainfo.info().name.as_ref()) {
+        path.parent().unwrap_or(Path::new(""))
+    } else {
+        path
+    };
+    let report = storage::verify(&metainfo, root, threads).map_err(|e| anyhow!("Couldn't verify {}: {}", path.display(), e))?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for file in &report.files {
+            println!("{}: {} good, {} bad, {} missing", file.path.display(), file.good, file.bad, file.missing);
         }
+        println!("Pieces: {} good, {} bad, {} missing of {}", report.count(PieceStatus::Good), report.count(PieceStatus::Bad),
+                 report.count(PieceStatus::Missing), report.pieces.len());
+    }
+    if !report.is_complete() {
+        return Err(anyhow!("{} of {} pieces failed verification", report.pieces.len() - report.count(PieceStatus::Good), report.pieces.len()));
+    }
+    Ok(())
+}
+
+/// Audits a torrent file for non-canonical bencode, printing every issue found
+pub fn validate_torrent_file(file_name: String) -> Result<()> {
+    let bytes = fs::read(&file_name).map_err(|e| anyhow!("Error opening file: {}", e))?;
+    let decoded = decode_document(&bytes, Mode::Lenient)
+        .map_err(|e| anyhow!("Wasn't able to decode the bencoded file: {}", e))?;
+
+    if decoded.warnings.is_empty() {
+        println!("Validation: {} is canonical bencode", file_name);
+        return Ok(());
     }
-}
\ No newline at end of file
+    for warning in &decoded.warnings {
+        println!("Validation warning: {}", warning);
+    }
+    Err(anyhow!("{} is not canonical bencode ({} issues)", file_name, decoded.warnings.len()))
+}
+
+/// Prints `value` as JSON, or only the part of it selected by the path expression `path`
+fn print_decoded_value(value: &BValue, path: Option<String>, binary: BinaryFormat) -> Result<()> {
+    match path {
+        Some(path) => println!("{}", query(value, &path)?.to_json_with(binary)),
+        None => println!("{}", value.to_json_with(binary)),
+    }
+    Ok(())
+}
+
+fn read_bencoded_file(file_name: &str) -> Result<BValue> {
+    let bytes = fs::read(file_name).map_err(|e| anyhow!("Error opening file: {}", e))?;
+    decode_bencoded_structure(&bytes)
+        .map_err(|e| anyhow!("Wasn't able to decode the bencoded file {}: {}", file_name, e))
+}
+
+/// Prints a bencoded file, or the part `path` selects, as JSON or as an annotated tree
+pub fn inspect_file(file_name: String, path: Option<String>, binary: BinaryFormat, tree: bool) -> Result<()> {
+    let value = read_bencoded_file(&file_name)?;
+    if !tree {
+        return print_decoded_value(&value, path, binary);
+    }
+    match path {
+        Some(path) => print!("{}", render_tree(&query(&value, &path)?)),
+        None => print!("{}", render_tree(&value)),
+    }
+    Ok(())
+}
+
+/// Prints the keys added, removed and changed going from `old_file` to `new_file`
+pub fn diff_files(old_file: String, new_file: String) -> Result<()> {
+    let changes = diff(&read_bencoded_file(&old_file)?, &read_bencoded_file(&new_file)?);
+    if changes.is_empty() {
+        println!("No differences");
+    }
+    for change in changes {
+        println!("{}", change);
+    }
+    Ok(())
+}
+
+/// Converts JSON in the `decode` output format, read from `input_file` or stdin, to bencode on stdout
+pub fn encode_json(input_file: Option<String>) -> Result<()> {
+    let json_text = match input_file {
+        Some(file_name) => fs::read_to_string(file_name).map_err(|e| anyhow!("Error opening file: {}", e))?,
+        None => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+    let json: serde_json::Value = serde_json::from_str(&json_text).map_err(|e| anyhow!("Invalid JSON: {}", e))?;
+    let value = BValue::from_json(&json)?;
+    std::io::stdout().write_all(&encode_bencoded_structure(&value))?;
+    Ok(())
+}
+
+pub fn print_bencoded_string(string: String, path: Option<String>, binary: BinaryFormat) -> Result<()> {
+    let value = decode_bencoded_structure(string.as_bytes())
+        .map_err(|e| anyhow!("Wasn't able to decode bencoded string: {}", e))?;
+    print_decoded_value(&value, path, binary)
+}
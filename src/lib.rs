@@ -0,0 +1,7 @@
+//! BitTorrent client library: bencode, torrent metadata, magnet links, the peer protocol, payload storage and the CLI command implementations
+pub mod bencode;
+pub mod commands;
+pub mod magnet;
+pub mod peer;
+pub mod storage;
+pub mod torrent;
@@ -0,0 +1,321 @@
+//! Magnet links (BEP 9, BEP 53 and the v2 `btmh` form from BEP 52)
+
+use crate::torrent::Metainfo;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Multihash prefix of a v2 info hash: SHA-256 (0x12), 32 bytes long (0x20)
+const SHA256_MULTIHASH: [u8; 2] = [0x12, 0x20];
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A `magnet:?` link. Parsed links always carry at least one of the two info hashes; one
+/// built by hand may have neither.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MagnetLink {
+    /// v1 info hash from `xt=urn:btih:`, hex or base32 encoded
+    pub info_hash: Option<[u8; 20]>,
+    /// v2 info hash from `xt=urn:btmh:`, a hex SHA-256 multihash
+    pub info_hash_v2: Option<[u8; 32]>,
+    /// `dn`
+    pub display_name: Option<String>,
+    /// `tr`
+    pub trackers: Vec<String>,
+    /// `ws`
+    pub web_seeds: Vec<String>,
+    /// `x.pe`, as `host:port`
+    pub peers: Vec<String>,
+    /// `so`: indices of the files to download (BEP 53)
+    pub select_only: Vec<RangeInclusive<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MagnetError {
+    #[error("not a magnet link")]
+    NotAMagnet,
+    #[error("magnet link has no btih or btmh info hash")]
+    MissingHash,
+    #[error("invalid info hash {0:?}")]
+    BadHash(String),
+    #[error("invalid percent-encoding in {0:?}")]
+    BadEscape(String),
+    #[error("invalid file selection {0:?}")]
+    BadSelection(String),
+}
+
+impl MagnetLink {
+    /// The link describing `metainfo`'s swarm, with its name, trackers and web seeds
+    pub fn from_metainfo(metainfo: &Metainfo) -> MagnetLink {
+        let mut trackers: Vec<String> = Vec::new();
+        let all_trackers = metainfo
+            .announce
+            .iter()
+            .chain(metainfo.announce_list.iter().flatten());
+        for tracker in all_trackers {
+            if !trackers.contains(tracker) {
+                trackers.push(tracker.clone());
+            }
+        }
+        MagnetLink {
+            info_hash: metainfo.info().has_v1().then_some(metainfo.info_hash),
+            info_hash_v2: metainfo.info_hash_v2,
+            display_name: Some(metainfo.info().name.clone()),
+            trackers,
+            web_seeds: metainfo.url_list.clone(),
+            peers: Vec::new(),
+            select_only: Vec::new(),
+        }
+    }
+
+    /// The 20-byte hash used with trackers and peers, `None` for a link without either hash;
+    /// see [`Metainfo::swarm_hash`]
+    pub fn swarm_hash(&self) -> Option<[u8; 20]> {
+        self.info_hash.or_else(|| self.truncated_v2())
+    }
+
+    /// Every hash the swarm may be found under, like [`Metainfo::swarm_hashes`]
+    pub fn swarm_hashes(&self) -> Vec<[u8; 20]> {
+        self.info_hash
+            .into_iter()
+            .chain(self.truncated_v2())
+            .collect()
+    }
+
+    /// The v2 hash cut to 20 bytes, as it appears in handshakes and tracker requests
+    fn truncated_v2(&self) -> Option<[u8; 20]> {
+        self.info_hash_v2.map(|v2| std::array::from_fn(|i| v2[i]))
+    }
+
+    /// Whether `info_bytes` is the info dictionary this link names: its SHA-1 must match the
+    /// v1 hash, or for v2-only links its SHA-256 the v2 hash
+    pub fn matches_metadata(&self, info_bytes: &[u8]) -> bool {
+        match (self.info_hash, self.info_hash_v2) {
+            (Some(v1), _) => Sha1::digest(info_bytes)[..] == v1,
+            (None, Some(v2)) => Sha256::digest(info_bytes)[..] == v2,
+            (None, None) => false,
+        }
+    }
+
+    /// Whether file `index` is selected for download; everything is when there's no `so`
+    pub fn is_selected(&self, index: usize) -> bool {
+        self.select_only.is_empty() || self.select_only.iter().any(|range| range.contains(&index))
+    }
+}
+
+impl FromStr for MagnetLink {
+    type Err = MagnetError;
+
+    fn from_str(link: &str) -> Result<Self, Self::Err> {
+        let query = link
+            .strip_prefix("magnet:?")
+            .ok_or(MagnetError::NotAMagnet)?;
+        let mut magnet = MagnetLink::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value, key == "dn")?;
+            match key {
+                // Hybrid links repeat `xt`, sometimes numbered as `xt.1`, `xt.2`...
+                _ if key == "xt" || key.starts_with("xt.") => {
+                    parse_exact_topic(&value, &mut magnet)?
+                }
+                "dn" => magnet.display_name = Some(value),
+                "tr" => magnet.trackers.push(value),
+                "ws" => magnet.web_seeds.push(value),
+                "x.pe" => magnet.peers.push(value),
+                "so" => magnet.select_only = parse_selection(&value)?,
+                _ => {}
+            }
+        }
+        if magnet.info_hash.is_none() && magnet.info_hash_v2.is_none() {
+            return Err(MagnetError::MissingHash);
+        }
+        Ok(magnet)
+    }
+}
+
+impl fmt::Display for MagnetLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut params = Vec::new();
+        if let Some(info_hash) = self.info_hash {
+            params.push(format!("xt=urn:btih:{}", hex::encode(info_hash)));
+        }
+        if let Some(info_hash_v2) = self.info_hash_v2 {
+            let multihash = [&SHA256_MULTIHASH[..], &info_hash_v2].concat();
+            params.push(format!("xt=urn:btmh:{}", hex::encode(multihash)));
+        }
+        if let Some(name) = &self.display_name {
+            params.push(format!("dn={}", percent_encode(name)));
+        }
+        for (key, values) in [
+            ("tr", &self.trackers),
+            ("ws", &self.web_seeds),
+            ("x.pe", &self.peers),
+        ] {
+            params.extend(
+                values
+                    .iter()
+                    .map(|value| format!("{}={}", key, percent_encode(value))),
+            );
+        }
+        if !self.select_only.is_empty() {
+            let ranges: Vec<String> = self
+                .select_only
+                .iter()
+                .map(|range| match range.start() == range.end() {
+                    true => range.start().to_string(),
+                    false => format!("{}-{}", range.start(), range.end()),
+                })
+                .collect();
+            params.push(format!("so={}", ranges.join(",")));
+        }
+        write!(f, "magnet:?{}", params.join("&"))
+    }
+}
+
+fn parse_exact_topic(topic: &str, magnet: &mut MagnetLink) -> Result<(), MagnetError> {
+    let bad_hash = || MagnetError::BadHash(topic.to_string());
+    if let Some(hash) = topic.strip_prefix("urn:btih:") {
+        let bytes = match hash.len() {
+            40 => hex::decode(hash).map_err(|_| bad_hash())?,
+            32 => base32_decode(hash).ok_or_else(bad_hash)?,
+            _ => return Err(bad_hash()),
+        };
+        magnet.info_hash = Some(bytes.try_into().map_err(|_| bad_hash())?);
+    } else if let Some(hash) = topic.strip_prefix("urn:btmh:") {
+        let bytes = hex::decode(hash).map_err(|_| bad_hash())?;
+        let digest = bytes
+            .strip_prefix(&SHA256_MULTIHASH[..])
+            .ok_or_else(bad_hash)?;
+        magnet.info_hash_v2 = Some(digest.try_into().map_err(|_| bad_hash())?);
+    }
+    Ok(())
+}
+
+/// Parses a BEP 53 selection such as `0,2,4-6`
+fn parse_selection(selection: &str) -> Result<Vec<RangeInclusive<usize>>, MagnetError> {
+    let bad_selection = || MagnetError::BadSelection(selection.to_string());
+    selection
+        .split(',')
+        .map(|part| {
+            let (start, end) = part.split_once('-').unwrap_or((part, part));
+            let start: usize = start.parse().map_err(|_| bad_selection())?;
+            let end: usize = end.parse().map_err(|_| bad_selection())?;
+            match start <= end {
+                true => Ok(start..=end),
+                false => Err(bad_selection()),
+            }
+        })
+        .collect()
+}
+
+/// RFC 4648 base32 without padding, as used by older `btih` links
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+fn percent_decode(text: &str, plus_is_space: bool) -> Result<String, MagnetError> {
+    let bad_escape = || MagnetError::BadEscape(text.to_string());
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'%' => {
+                let escape = [
+                    input.next().ok_or_else(bad_escape)?,
+                    input.next().ok_or_else(bad_escape)?,
+                ];
+                let escape = std::str::from_utf8(&escape).map_err(|_| bad_escape())?;
+                bytes.push(u8::from_str_radix(escape, 16).map_err(|_| bad_escape())?);
+            }
+            b'+' if plus_is_space => bytes.push(b' '),
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| bad_escape())
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'0'..=b'9' | b'A'..=b'Z' | b'a'..=b'z' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX_HASH: &str = "d69f91e6b2ae4c542468d1073a71d4ea13879a7f";
+
+    #[test]
+    fn parses_and_formats_links() {
+        let link = format!(
+            "magnet:?xt=urn:btih:{}&dn=magnet+file.iso&tr=http%3A%2F%2Ftracker%2Fannounce\
+             &ws=http%3A%2F%2Fseed%2F&x.pe=10.0.0.1%3A6881&so=0,2,4-6&x.unknown=1",
+            HEX_HASH
+        );
+        let magnet: MagnetLink = link.parse().unwrap();
+        assert_eq!(hex::encode(magnet.swarm_hash().unwrap()), HEX_HASH);
+        assert_eq!(magnet.display_name.as_deref(), Some("magnet file.iso"));
+        assert_eq!(magnet.trackers, ["http://tracker/announce"]);
+        assert_eq!(magnet.web_seeds, ["http://seed/"]);
+        assert_eq!(magnet.peers, ["10.0.0.1:6881"]);
+        assert_eq!(magnet.select_only, [0..=0, 2..=2, 4..=6]);
+        assert!(magnet.is_selected(5) && !magnet.is_selected(3));
+        assert_eq!(magnet.to_string().parse::<MagnetLink>().unwrap(), magnet);
+    }
+
+    #[test]
+    fn accepts_base32_and_v2_hashes() {
+        let base32 = "22PZDZVSVZGFIJDI2EDTU4OU5IJYPGT7";
+        let magnet: MagnetLink = format!("magnet:?xt=urn:btih:{}", base32.to_lowercase())
+            .parse()
+            .unwrap();
+        assert_eq!(hex::encode(magnet.info_hash.unwrap()), HEX_HASH);
+
+        let v2 = "1220".to_string() + &"ab".repeat(32);
+        let magnet: MagnetLink = format!("magnet:?xt=urn:btmh:{}", v2).parse().unwrap();
+        assert_eq!(magnet.info_hash, None);
+        assert_eq!(magnet.swarm_hash(), Some([0xab; 20]));
+        assert_eq!(magnet.swarm_hashes(), [[0xab; 20]]);
+
+        // Nothing stops a hand-built link from having no hash at all
+        let empty = MagnetLink::default();
+        assert_eq!(empty.swarm_hash(), None);
+        assert!(empty.swarm_hashes().is_empty());
+
+        assert_eq!(
+            "magnet:?dn=x".parse::<MagnetLink>(),
+            Err(MagnetError::MissingHash)
+        );
+        assert!("magnet:?xt=urn:btih:abc".parse::<MagnetLink>().is_err());
+        assert!("magnet:?xt=urn:btmh:1114".parse::<MagnetLink>().is_err());
+        assert_eq!(
+            "http://x".parse::<MagnetLink>(),
+            Err(MagnetError::NotAMagnet)
+        );
+    }
+}
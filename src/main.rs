@@ -1,116 +1,372 @@
-/// Main function, associated Command types and their entry points
-mod bencode;
-mod commands;
-mod torrent;
-
-use crate::commands::{print_bencoded_string, establish_peer_connection, fetch_torrent_info, fetch_torrent_peers, download_torrent_piece};
+//! Main function, associated Command types and their entry points
+use bittorrent_starter_rust::bencode::BinaryFormat;
+use bittorrent_starter_rust::commands::{print_bencoded_string, establish_peer_connection, fetch_torrent_info, fetch_torrent_peers, download_torrent_piece, download_torrent,
+                                        validate_torrent_file, inspect_file, diff_files, encode_json, create_torrent_file, edit_torrent_file,
+                                        fetch_magnet_info, verify_payload};
+use bittorrent_starter_rust::torrent::create::CreateOptions;
+use bittorrent_starter_rust::torrent::edit::TorrentEdit;
+use std::fmt::Display;
+use std::process::ExitCode;
 use std::str::FromStr;
 use std::{env, fs};
 use std::net::SocketAddrV4;
 
 #[derive(Debug)]
 pub enum Command {
-    Decode(String),
-    Info(String),
+    Decode { bencoded_value: String, path: Option<String>, binary: BinaryFormat },
+    Inspect { file_name: String, path: Option<String>, binary: BinaryFormat, tree: bool },
+    Diff { old_file: String, new_file: String },
+    Encode { input_file: Option<String> },
+    Info { file_name: String, validate: bool },
     Peers(String),
     Handshake { file_name: String, peer_address: SocketAddrV4 },
-    DownloadPiece { file_name: String, output_file_path: String, piece: u32 }
+    DownloadPiece { file_name: String, output_file_path: String, piece: u32, queue_depth: Option<usize> },
+    Download { file_name: String, output_dir: String, queue_depth: Option<usize> },
+    Create { path: String, output_file: String, options: CreateOptions },
+    Edit { file_name: String, output_file: Option<String>, edit: TorrentEdit },
+    MagnetInfo { link: String, output_file: Option<String> },
+    Verify { file_name: String, path: String, threads: Option<usize>, json: bool }
+}
+
+/// Splits the arguments after the command name into positional arguments and `--flags`
+fn split_flags(args: &[String]) -> (Vec<String>, Vec<String>) {
+    args.iter().cloned().partition(|arg| !arg.starts_with("--"))
+}
+
+fn binary_format(flags: &[String]) -> BinaryFormat {
+    if flags.iter().any(|flag| flag == "--hex") {
+        BinaryFormat::Hex
+    } else {
+        BinaryFormat::Base64
+    }
+}
+
+/// Torrent sources are either a `.torrent` file or a magnet link
+fn source_exists(source: &str) -> bool {
+    source.starts_with("magnet:") || fs::metadata(source).is_ok()
+}
+
+/// Tracker tiers from `--name=url,url` flags, one tier per flag
+fn tracker_tiers(flags: &[String], name: &str) -> Vec<Vec<String>> {
+    flag_values(flags, name)
+        .iter()
+        .map(|tier| tier.split(',').map(str::to_string).collect())
+        .collect()
+}
+
+/// Values of every `--name=value` flag with the given name
+fn flag_values(flags: &[String], name: &str) -> Vec<String> {
+    let prefix = format!("{}=", name);
+    flags.iter().filter_map(|flag| flag.strip_prefix(&prefix)).map(str::to_string).collect()
+}
+
+fn flag_value(flags: &[String], name: &str) -> Option<String> {
+    flag_values(flags, name).pop()
+}
+
+fn parse_flag<T: FromStr>(flags: &[String], name: &str) -> Result<Option<T>, String> {
+    flag_value(flags, name)
+        .map(|value| value.parse().map_err(|_| format!("Invalid value '{}' for {}", value, name)))
+        .transpose()
 }
 
 impl FromStr for Command {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let args: Vec<String> = env::args().collect();
+        let (positional, flags) = split_flags(&args[2..]);
 
         match s.to_lowercase().as_str() {
             "decode" => {
-                if args.len() < 3 {
+                if positional.is_empty() {
                     return Err("Bencoded string required".to_string());
                 }
-                Ok(Command::Decode(args[2].clone()))
-            }
-            "info" | "peers" | "handshake" => {
-                if s == "handshake" && args.len() < 4 {
-                    return Err("File name and peer IP:port required".to_string());
-                } else if args.len() < 3 {
-                    return Err("File name required".to_string());
-                }
-                match fs::metadata(&args[2]) {
-                    Ok(_) => {
-                        if s == "info" {
-                            Ok(Command::Info(args[2].clone()))
-                        } else if s == "peers" {
-                            Ok(Command::Peers(args[2].clone()))
-                        } else if s == "handshake" {
-                            Ok(Command::Handshake {
-                                file_name: args[2].clone(),
-                                peer_address: args[3].clone().parse().expect("Not a valid address!"),
-                            })
-                        } else {
-                            Err(format!("This isn't right."))
-                        }
+                Ok(Command::Decode {
+                    bencoded_value: positional[0].clone(),
+                    path: positional.get(1).cloned(),
+                    binary: binary_format(&flags),
+                })
+            }
+            "info" | "inspect" => {
+                let file_name = match positional.first() {
+                    Some(file_name) => file_name,
+                    None => return Err("File name required".to_string()),
+                };
+                if fs::metadata(file_name).is_err() {
+                    return Err(format!("File '{}' not found", file_name));
+                }
+                if s == "info" {
+                    Ok(Command::Info {
+                        file_name: file_name.clone(),
+                        validate: flags.iter().any(|flag| flag == "--validate"),
+                    })
+                } else {
+                    Ok(Command::Inspect {
+                        file_name: file_name.clone(),
+                        path: positional.get(1).cloned(),
+                        binary: binary_format(&flags),
+                        tree: flags.iter().any(|flag| flag == "--tree"),
+                    })
+                }
+            }
+            "encode" => {
+                let input_file = positional.first().filter(|file_name| *file_name != "-").cloned();
+                if let Some(file_name) = &input_file {
+                    if fs::metadata(file_name).is_err() {
+                        return Err(format!("File '{}' not found", file_name));
                     }
-                    Err(_) => Err(format!("File '{}' not found", &args[2])),
                 }
+                Ok(Command::Encode { input_file })
             }
-            "download_piece" => {
-                if args.len() < 6 {
-                    return Err("Usage: 'download_piece -o /tmp/test-piece-0 sample.torrent 0'".to_string());
+            "diff" => {
+                if positional.len() < 2 {
+                    return Err("Two files to compare required".to_string());
+                }
+                for file_name in &positional[..2] {
+                    if fs::metadata(file_name).is_err() {
+                        return Err(format!("File '{}' not found", file_name));
+                    }
                 }
-                match fs::metadata(&args[4]) {
-                    Ok(_) => {
+                Ok(Command::Diff { old_file: positional[0].clone(), new_file: positional[1].clone() })
+            }
+            "peers" => {
+                let file_name = match positional.as_slice() {
+                    [file_name] => file_name,
+                    _ => return Err("File name required".to_string()),
+                };
+                match source_exists(file_name) {
+                    true => Ok(Command::Peers(file_name.clone())),
+                    false => Err(format!("File '{}' not found", file_name)),
+                }
+            }
+            "handshake" => {
+                let (file_name, peer_address) = match positional.as_slice() {
+                    [file_name, peer_address] => (file_name, peer_address),
+                    _ => return Err("File name and peer IP:port required".to_string()),
+                };
+                match source_exists(file_name) {
+                    true => Ok(Command::Handshake {
+                        file_name: file_name.clone(),
+                        peer_address: peer_address.parse().map_err(|_| format!("Invalid peer address '{}'", peer_address))?,
+                    }),
+                    false => Err(format!("File '{}' not found", file_name)),
+                }
+            }
+            "download_piece" => {
+                let (output_file_path, file_name, piece) = match positional.as_slice() {
+                    [o, output_file_path, file_name, piece] if o == "-o" => (output_file_path, file_name, piece),
+                    _ => return Err("Usage: 'download_piece -o /tmp/test-piece-0 sample.torrent 0'".to_string()),
+                };
+                match source_exists(file_name) {
+                    true => {
                         Ok(Command::DownloadPiece {
-                            file_name: args[4].clone(),
-                            output_file_path: args[3].clone(),
-                            piece: args[5].clone().parse().unwrap(),
+                            file_name: file_name.clone(),
+                            output_file_path: output_file_path.clone(),
+                            piece: piece.parse().map_err(|_| format!("Invalid piece index '{}'", piece))?,
+                            queue_depth: parse_flag(&flags, "--queue-depth")?,
                         })
                     }
-                    Err(_) => Err(format!("File '{}' not found", &args[4])),
+                    false => Err(format!("File '{}' not found", file_name)),
                 }
             }
+            "download" => {
+                let (output_dir, file_name) = match positional.as_slice() {
+                    [o, output_dir, file_name] if o == "-o" => (output_dir, file_name),
+                    _ => return Err("Usage: 'download -o /tmp/downloads sample.torrent'".to_string()),
+                };
+                match source_exists(file_name) {
+                    true => Ok(Command::Download {
+                        file_name: file_name.clone(),
+                        output_dir: output_dir.clone(),
+                        queue_depth: parse_flag(&flags, "--queue-depth")?,
+                    }),
+                    false => Err(format!("File '{}' not found", file_name)),
+                }
+            }
+            "create" => {
+                let output_index = positional.iter().position(|arg| arg == "-o");
+                let (path, output_file) = match (positional.first(), output_index.and_then(|i| positional.get(i + 1))) {
+                    (Some(path), Some(output_file)) if output_index != Some(0) => (path, output_file),
+                    _ => return Err("Usage: 'create <path> -o out.torrent'".to_string()),
+                };
+                if fs::metadata(path).is_err() {
+                    return Err(format!("File '{}' not found", path));
+                }
+                let options = CreateOptions {
+                    piece_length: parse_flag(&flags, "--piece-length")?,
+                    trackers: tracker_tiers(&flags, "--tracker"),
+                    comment: flag_value(&flags, "--comment"),
+                    private: flags.iter().any(|flag| flag == "--private"),
+                    source: flag_value(&flags, "--source"),
+                    web_seeds: flag_values(&flags, "--web-seed"),
+                    threads: parse_flag(&flags, "--threads")?,
+                };
+                Ok(Command::Create { path: path.clone(), output_file: output_file.clone(), options })
+            }
+            "edit" => {
+                let file_name = match positional.first() {
+                    Some(file_name) if file_name != "-o" => file_name,
+                    _ => return Err("Usage: 'edit <torrent> [-o out.torrent]'".to_string()),
+                };
+                if fs::metadata(file_name).is_err() {
+                    return Err(format!("File '{}' not found", file_name));
+                }
+                let output_file = match positional.iter().position(|arg| arg == "-o") {
+                    Some(i) => Some(positional.get(i + 1).ok_or("Output file required after -o")?.clone()),
+                    None => None,
+                };
+                let web_seeds = flag_values(&flags, "--web-seed");
+                let edit = TorrentEdit {
+                    trackers: Some(tracker_tiers(&flags, "--tracker")).filter(|tiers| !tiers.is_empty()),
+                    add_trackers: flag_values(&flags, "--add-tracker"),
+                    remove_trackers: flag_values(&flags, "--remove-tracker"),
+                    comment: flag_value(&flags, "--comment"),
+                    web_seeds: (!web_seeds.is_empty())
+                        .then(|| web_seeds.into_iter().filter(|url| !url.is_empty()).collect()),
+                    source: flag_value(&flags, "--source"),
+                    private: if flags.iter().any(|flag| flag == "--private") {
+                        Some(true)
+                    } else if flags.iter().any(|flag| flag == "--public") {
+                        Some(false)
+                    } else {
+                        None
+                    },
+                };
+                Ok(Command::Edit { file_name: file_name.clone(), output_file, edit })
+            }
+            "magnet_info" => {
+                let link = match positional.first() {
+                    Some(link) if link.starts_with("magnet:") => link.clone(),
+                    _ => return Err("Usage: 'magnet_info <magnet link> [-o out.torrent]'".to_string()),
+                };
+                let output_file = match positional.iter().position(|arg| arg == "-o") {
+                    Some(i) => Some(positional.get(i + 1).ok_or("Output file required after -o")?.clone()),
+                    None => None,
+                };
+                Ok(Command::MagnetInfo { link, output_file })
+            }
+            "verify" => {
+                let (file_name, path) = match (positional.first(), positional.get(1)) {
+                    (Some(file_name), Some(path)) => (file_name, path),
+                    _ => return Err("Usage: 'verify <torrent> <path>'".to_string()),
+                };
+                if fs::metadata(file_name).is_err() {
+                    return Err(format!("File '{}' not found", file_name));
+                }
+                Ok(Command::Verify {
+                    file_name: file_name.clone(),
+                    path: path.clone(),
+                    threads: parse_flag(&flags, "--threads")?,
+                    json: flags.iter().any(|flag| flag == "--json"),
+                })
+            }
             _ => Err("Invalid command!".to_string()),
         }
     }
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 || &args[2] == "help" {
+    if args.len() < 2 || args[1] == "help" {
         eprintln!(
-            "Usage: decode [bencoded string], info [torrent file], peers [torrent file]\
-        , handshake [torrent file] [peer ip: peer port]"
+            "Usage: decode [bencoded string] [path] [--hex], info [--validate] [torrent file]\
+        , inspect [file] [path] [--hex] [--tree], diff [file] [file], encode [json file, default stdin], peers [torrent file or magnet link], handshake [torrent file or magnet link] [peer ip: peer port], download_piece -o [output file] [torrent file] [piece index] [--queue-depth=n]\
+        , download -o [output directory] [torrent file or magnet link] [--queue-depth=n]\
+        , create [path] -o [torrent file] [--tracker=url[,url...]] [--piece-length=n] [--comment=text] [--private] [--source=text] [--web-seed=url] [--threads=n]\
+        , edit [torrent file] [-o torrent file] [--tracker=url[,url...]] [--add-tracker=url] [--remove-tracker=url] [--comment=text] [--source=text] [--web-seed=url] [--private|--public]\
+        , magnet_info [magnet link] [-o torrent file], verify [torrent file] [path] [--threads=n] [--json]"
         );
-        return;
+        return match args.len() < 2 {
+            true => ExitCode::FAILURE,
+            false => ExitCode::SUCCESS,
+        };
     }
 
     let command = &args[1];
+    let mut status = ExitCode::SUCCESS;
 
     match Command::from_str(command) {
         Ok(command) => match command {
-            Command::Decode(bencoded_value) => {
-                print_bencoded_string(bencoded_value)
+            Command::Decode { bencoded_value, path, binary } => {
+                if let Err(err) = print_bencoded_string(bencoded_value, path, binary) {
+                    status = fail(err);
+                }
+            }
+            Command::Inspect { file_name, path, binary, tree } => {
+                if let Err(err) = inspect_file(file_name, path, binary, tree) {
+                    status = fail(err);
+                }
             }
-            Command::Info(file_name) => {
-                if let Err(err) = fetch_torrent_info(file_name, true) {
-                    eprintln!("Error: {}", err);
+            Command::Encode { input_file } => {
+                if let Err(err) = encode_json(input_file) {
+                    status = fail(err);
+                }
+            }
+            Command::Diff { old_file, new_file } => {
+                if let Err(err) = diff_files(old_file, new_file) {
+                    status = fail(err);
+                }
+            }
+            Command::Info { file_name, validate } => {
+                if let Err(err) = fetch_torrent_info(file_name.clone(), true) {
+                    status = fail(err);
+                }
+                if validate {
+                    if let Err(err) = validate_torrent_file(file_name) {
+                        status = fail(err);
+                    }
                 }
             }
             Command::Peers(file_name) => {
                 if let Err(err) = fetch_torrent_peers(file_name, true) {
-                    eprintln!("Error: {}", err);
+                    status = fail(err);
                 }
             },
             Command::Handshake { file_name, peer_address } =>
                 if let Err(err) = establish_peer_connection(file_name, peer_address, true) {
-                    eprintln!("Error: {}", err);
+                    status = fail(err);
+                }
+            Command::DownloadPiece { file_name, output_file_path, piece, queue_depth } => {
+                match download_torrent_piece(file_name, output_file_path, piece, queue_depth) {
+                    Ok(message) => println!("{}", message),
+                    Err(err) => status = fail(err),
+                }
+            }
+            Command::Download { file_name, output_dir, queue_depth } => {
+                match download_torrent(file_name, output_dir, queue_depth) {
+                    Ok(message) => println!("{}", message),
+                    Err(err) => status = fail(err),
+                }
+            }
+            Command::Create { path, output_file, options } => {
+                if let Err(err) = create_torrent_file(path, output_file, options) {
+                    status = fail(err);
                 }
-            Command::DownloadPiece { file_name, output_file_path, piece } => {
-                if let Err(err) = download_torrent_piece(file_name, output_file_path, piece) {
-                    eprintln!("Error: {}", err);
+            }
+            Command::Edit { file_name, output_file, edit } => {
+                if let Err(err) = edit_torrent_file(file_name, output_file, edit) {
+                    status = fail(err);
+                }
+            }
+            Command::MagnetInfo { link, output_file } => {
+                if let Err(err) = fetch_magnet_info(link, output_file) {
+                    status = fail(err);
+                }
+            }
+            Command::Verify { file_name, path, threads, json } => {
+                if let Err(err) = verify_payload(file_name, path, threads, json) {
+                    status = fail(err);
                 }
             }
         },
-        _ => {}
+        Err(err) => status = fail(err),
     }
+    status
+}
+
+/// Reports a failed command; the process then exits with a nonzero status
+fn fail(err: impl Display) -> ExitCode {
+    eprintln!("Error: {}", err);
+    ExitCode::FAILURE
 }
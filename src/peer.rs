@@ -0,0 +1,84 @@
+//! Talking to other peers over the peer wire protocol (BEP 3) and its extensions
+
+pub mod download;
+pub mod handshake;
+pub mod message;
+pub mod metadata;
+
+/// A stand-in for a peer's socket in tests
+#[cfg(test)]
+pub(crate) mod scripted {
+    use super::message::Message;
+    use std::io::{self, Cursor, Read, Write};
+
+    /// Replies with canned bytes and records what we send it. Running out of input reads as
+    /// the peer hanging up, or with [`ScriptedPeer::timing_out`] as a read timeout expiring.
+    pub struct ScriptedPeer {
+        pub incoming: Cursor<Vec<u8>>,
+        pub outgoing: Vec<u8>,
+        chunk_size: usize,
+        times_out: bool,
+    }
+
+    impl ScriptedPeer {
+        pub fn new(incoming: &[u8]) -> ScriptedPeer {
+            ScriptedPeer {
+                incoming: Cursor::new(incoming.to_vec()),
+                outgoing: Vec::new(),
+                chunk_size: usize::MAX,
+                times_out: false,
+            }
+        }
+
+        pub fn with_messages(messages: &[Message]) -> ScriptedPeer {
+            ScriptedPeer::new(
+                &messages
+                    .iter()
+                    .flat_map(Message::encode)
+                    .collect::<Vec<_>>(),
+            )
+        }
+
+        /// Hands out at most `chunk_size` bytes per read, as a socket might
+        pub fn chunked(self, chunk_size: usize) -> ScriptedPeer {
+            ScriptedPeer { chunk_size, ..self }
+        }
+
+        pub fn timing_out(self) -> ScriptedPeer {
+            ScriptedPeer {
+                times_out: true,
+                ..self
+            }
+        }
+
+        /// The messages we sent
+        pub fn sent(&self) -> Vec<Message> {
+            let mut outgoing = &self.outgoing[..];
+            let mut messages = Vec::new();
+            while !outgoing.is_empty() {
+                messages.push(Message::read_from(&mut outgoing).unwrap());
+            }
+            messages
+        }
+    }
+
+    impl Read for ScriptedPeer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let chunk = buf.len().min(self.chunk_size);
+            match self.incoming.read(&mut buf[..chunk])? {
+                0 if self.times_out && chunk > 0 => Err(io::ErrorKind::WouldBlock.into()),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for ScriptedPeer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
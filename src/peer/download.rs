@@ -0,0 +1,291 @@
+//! Downloading one piece from a peer: announcing interest, waiting to be unchoked and
+//! keeping a queue of block requests in flight until the piece is complete
+
+use super::message::{Message, MessageError};
+use sha1::{Digest, Sha1};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+/// Pieces are requested in blocks of this size; only a piece's last block may be shorter
+pub const BLOCK_SIZE: u32 = 16 * 1024;
+/// Block requests kept in flight when the caller doesn't choose
+pub const DEFAULT_QUEUE_DEPTH: usize = 5;
+/// Messages in a row that don't move the download forward (keep-alives, `have`s for other
+/// pieces, ...) before the peer is treated as having gone quiet
+pub const MAX_IDLE_MESSAGES: usize = 100;
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("connection failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Message(MessageError),
+    #[error("peer went quiet before the piece was complete")]
+    Timeout,
+    #[error("peer doesn't have piece {0}")]
+    MissingPiece(u32),
+    #[error("peer sent a block that wasn't requested: {length} bytes at {begin}")]
+    UnexpectedBlock { begin: u32, length: usize },
+    #[error("piece {0} doesn't match its hash")]
+    HashMismatch(u32),
+}
+
+/// Downloads piece `index`, `length` bytes long, and checks it against its SHA-1 `hash`.
+/// Blocks are only requested once the peer has announced the piece, in its bitfield or with a
+/// `have`; a bitfield without it ends the download, since the peer only has what it lists.
+/// Up to `queue_depth` block requests are kept in flight; a choke cancels them, and they are
+/// sent again once the peer unchokes us. A peer that only keeps the connection alive gives
+/// up after [`MAX_IDLE_MESSAGES`], as if the read had timed out.
+pub fn download_piece<S: Read + Write>(
+    stream: &mut S,
+    index: u32,
+    length: u32,
+    hash: &[u8],
+    queue_depth: usize,
+) -> Result<Vec<u8>, DownloadError> {
+    let block_count = length.div_ceil(BLOCK_SIZE) as usize;
+    let block_length = |block: usize| BLOCK_SIZE.min(length - block as u32 * BLOCK_SIZE);
+    let mut pending: VecDeque<usize> = (0..block_count).collect();
+    let mut in_flight: Vec<usize> = Vec::new();
+    let mut received = vec![false; block_count];
+    let mut piece = vec![0; length as usize];
+    let mut peer_has_piece = false;
+    let mut choked = true;
+    let mut idle = 0;
+
+    // The bitfield is optional, so interest is announced up front rather than in reply to it
+    Message::Interested.write_to(stream)?;
+    while received.contains(&false) {
+        if peer_has_piece && !choked {
+            while in_flight.len() < queue_depth.max(1) {
+                let Some(block) = pending.pop_front() else {
+                    break;
+                };
+                let request = Message::Request {
+                    index,
+                    begin: block as u32 * BLOCK_SIZE,
+                    length: block_length(block),
+                };
+                request.write_to(stream)?;
+                in_flight.push(block);
+            }
+        }
+
+        idle += 1;
+        if idle > MAX_IDLE_MESSAGES {
+            return Err(DownloadError::Timeout);
+        }
+        match Message::read_from(stream).map_err(timeout_or_message)? {
+            Message::Bitfield(bits) => {
+                peer_has_piece = bits
+                    .get(index as usize / 8)
+                    .is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0);
+                if !peer_has_piece {
+                    return Err(DownloadError::MissingPiece(index));
+                }
+                idle = 0;
+            }
+            Message::Have(have) if have == index && !peer_has_piece => {
+                peer_has_piece = true;
+                idle = 0;
+            }
+            Message::Unchoke if choked => {
+                choked = false;
+                idle = 0;
+            }
+            Message::Choke => {
+                // The peer drops our outstanding requests when it chokes us
+                choked = true;
+                for block in in_flight.drain(..).rev() {
+                    pending.push_front(block);
+                }
+            }
+            Message::Piece {
+                index: piece_index,
+                begin,
+                block,
+            } if piece_index == index => {
+                let number = (begin / BLOCK_SIZE) as usize;
+                let expected = begin % BLOCK_SIZE == 0
+                    && number < block_count
+                    && block.len() == block_length(number) as usize;
+                if !expected {
+                    return Err(DownloadError::UnexpectedBlock {
+                        begin,
+                        length: block.len(),
+                    });
+                }
+                // Blocks already in flight may still arrive after a choke
+                if !received[number] {
+                    piece[begin as usize..][..block.len()].copy_from_slice(&block);
+                    received[number] = true;
+                    in_flight.retain(|&b| b != number);
+                    pending.retain(|&b| b != number);
+                    idle = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if Sha1::digest(&piece)[..] != *hash {
+        return Err(DownloadError::HashMismatch(index));
+    }
+    Ok(piece)
+}
+
+/// Sockets report an expired read timeout as `WouldBlock` on Unix and `TimedOut` on Windows
+fn timeout_or_message(e: MessageError) -> DownloadError {
+    match e {
+        MessageError::Io(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            DownloadError::Timeout
+        }
+        e => DownloadError::Message(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::scripted::ScriptedPeer;
+
+    fn scripted(messages: &[Message]) -> ScriptedPeer {
+        ScriptedPeer::with_messages(messages).timing_out()
+    }
+
+    fn block(data: &[u8], begin: u32, length: u32) -> Message {
+        Message::Piece {
+            index: 3,
+            begin,
+            block: data[begin as usize..][..length as usize].to_vec(),
+        }
+    }
+
+    fn request(begin: u32, length: u32) -> Message {
+        Message::Request {
+            index: 3,
+            begin,
+            length,
+        }
+    }
+
+    #[test]
+    fn pipelines_requests_and_recovers_from_chokes() {
+        // Three blocks, the last one short
+        let length = 2 * BLOCK_SIZE + 100;
+        let data: Vec<u8> = (0..length).map(|i| (i % 251) as u8).collect();
+        let hash = Sha1::digest(&data);
+        let mut peer = scripted(&[
+            Message::Bitfield(vec![0b0001_0000]),
+            Message::Unchoke,
+            block(&data, BLOCK_SIZE, BLOCK_SIZE),
+            // Block 0 is lost to the choke and has to be asked for again
+            Message::Choke,
+            block(&data, 2 * BLOCK_SIZE, 100),
+            Message::Unchoke,
+            block(&data, 0, BLOCK_SIZE),
+        ]);
+
+        let piece = download_piece(&mut peer, 3, length, &hash, 2).unwrap();
+        assert_eq!(piece, data);
+        assert_eq!(
+            peer.sent(),
+            [
+                Message::Interested,
+                request(0, BLOCK_SIZE),
+                request(BLOCK_SIZE, BLOCK_SIZE),
+                request(2 * BLOCK_SIZE, 100),
+                request(0, BLOCK_SIZE),
+            ]
+        );
+    }
+
+    #[test]
+    fn works_with_peers_that_only_send_haves() {
+        let data = [7; 10];
+        let mut peer = scripted(&[
+            Message::Unchoke,
+            Message::Have(2),
+            Message::Have(3),
+            block(&data, 0, 10),
+        ]);
+        let piece = download_piece(&mut peer, 3, 10, &Sha1::digest(data), 5).unwrap();
+        assert_eq!(piece, data);
+        // Nothing is requested before the peer says it has the piece
+        assert_eq!(peer.sent(), [Message::Interested, request(0, 10)]);
+
+        let mut peer = scripted(&[Message::Unchoke, Message::Have(2)]);
+        assert!(matches!(
+            download_piece(&mut peer, 3, 10, &Sha1::digest(data), 5),
+            Err(DownloadError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn gives_up_on_peers_that_only_keep_the_connection_alive() {
+        let data = [7; 10];
+        let mut messages = vec![Message::Bitfield(vec![0xff]), Message::Unchoke];
+        messages.extend(std::iter::repeat_n(
+            Message::KeepAlive,
+            MAX_IDLE_MESSAGES - 1,
+        ));
+        messages.push(block(&data, 0, 10));
+        let mut peer = scripted(&messages);
+        let piece = download_piece(&mut peer, 3, 10, &Sha1::digest(data), 5).unwrap();
+        assert_eq!(piece, data);
+
+        // The block is still to come, so only the cap stops the wait
+        let mut messages = vec![Message::Bitfield(vec![0xff]), Message::Unchoke];
+        messages.extend(std::iter::repeat_n(Message::KeepAlive, MAX_IDLE_MESSAGES));
+        messages.push(block(&data, 0, 10));
+        let mut peer = scripted(&messages);
+        assert!(matches!(
+            download_piece(&mut peer, 3, 10, &Sha1::digest(data), 5),
+            Err(DownloadError::Timeout)
+        ));
+        let unread = block(&data, 0, 10).encode().len();
+        assert_eq!(
+            peer.incoming.position() as usize,
+            peer.incoming.get_ref().len() - unread
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_corrupt_pieces() {
+        let mut peer = scripted(&[Message::Bitfield(vec![0b1110_1111])]);
+        assert!(matches!(
+            download_piece(&mut peer, 3, 10, &[0; 20], 5),
+            Err(DownloadError::MissingPiece(3))
+        ));
+
+        let data = [7; 10];
+        let mut peer = scripted(&[
+            Message::Bitfield(vec![0xff]),
+            Message::Unchoke,
+            block(&data, 0, 10),
+        ]);
+        assert!(matches!(
+            download_piece(&mut peer, 3, 10, &[0; 20], 5),
+            Err(DownloadError::HashMismatch(3))
+        ));
+
+        let mut peer = scripted(&[
+            Message::Bitfield(vec![0xff]),
+            Message::Unchoke,
+            block(&data, 0, 4),
+        ]);
+        assert!(matches!(
+            download_piece(&mut peer, 3, 10, &Sha1::digest(data), 5),
+            Err(DownloadError::UnexpectedBlock {
+                begin: 0,
+                length: 4
+            })
+        ));
+    }
+}
@@ -0,0 +1,178 @@
+//! The 68-byte handshake that opens every peer connection: the protocol string, 8 reserved
+//! bytes flagging extensions, the info hash and the sender's peer id
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use thiserror::Error;
+
+pub const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+pub const HANDSHAKE_LENGTH: usize = 1 + PROTOCOL.len() + 8 + 20 + 20;
+
+/// Reserved bits, as (byte, mask) into the reserved bytes
+const DHT: (usize, u8) = (7, 0x01);
+const FAST: (usize, u8) = (7, 0x04);
+const EXTENSION_PROTOCOL: (usize, u8) = (5, 0x10);
+
+#[derive(Debug, Error)]
+pub enum HandshakeError {
+    #[error("connection failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("peer didn't complete the handshake in time")]
+    Timeout,
+    #[error("peer doesn't speak the BitTorrent protocol (sent {0:?})")]
+    BadProtocol(String),
+    #[error("peer answered for info hash {}", hex::encode(.0))]
+    InfoHashMismatch([u8; 20]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    pub reserved: [u8; 8],
+    pub info_hash: [u8; 20],
+    pub peer_id: [u8; 20],
+}
+
+impl Handshake {
+    /// Our handshake, which advertises the extension protocol and nothing else
+    pub fn new(info_hash: [u8; 20], peer_id: [u8; 20]) -> Handshake {
+        let mut reserved = [0; 8];
+        reserved[EXTENSION_PROTOCOL.0] |= EXTENSION_PROTOCOL.1;
+        Handshake {
+            reserved,
+            info_hash,
+            peer_id,
+        }
+    }
+
+    fn has_bit(&self, (byte, mask): (usize, u8)) -> bool {
+        self.reserved[byte] & mask != 0
+    }
+
+    /// BEP 5: the peer runs a DHT node and may send its port
+    pub fn supports_dht(&self) -> bool {
+        self.has_bit(DHT)
+    }
+
+    /// BEP 6: the peer understands the Fast extension's messages
+    pub fn supports_fast(&self) -> bool {
+        self.has_bit(FAST)
+    }
+
+    /// BEP 10: the peer accepts extension messages, such as `ut_metadata`
+    pub fn supports_extension_protocol(&self) -> bool {
+        self.has_bit(EXTENSION_PROTOCOL)
+    }
+
+    pub fn encode(&self) -> [u8; HANDSHAKE_LENGTH] {
+        let mut bytes = [0; HANDSHAKE_LENGTH];
+        bytes[0] = PROTOCOL.len() as u8;
+        bytes[1..20].copy_from_slice(PROTOCOL);
+        bytes[20..28].copy_from_slice(&self.reserved);
+        bytes[28..48].copy_from_slice(&self.info_hash);
+        bytes[48..].copy_from_slice(&self.peer_id);
+        bytes
+    }
+
+    /// Reads exactly one handshake, however the bytes are split across reads, and checks its
+    /// protocol string
+    pub fn read_from(reader: &mut impl Read) -> Result<Handshake, HandshakeError> {
+        let mut bytes = [0; HANDSHAKE_LENGTH];
+        reader.read_exact(&mut bytes).map_err(timeout_or_io)?;
+        if bytes[0] as usize != PROTOCOL.len() || &bytes[1..20] != PROTOCOL {
+            let length = (bytes[0] as usize).min(HANDSHAKE_LENGTH - 1);
+            let protocol = String::from_utf8_lossy(&bytes[1..1 + length]).into_owned();
+            return Err(HandshakeError::BadProtocol(protocol));
+        }
+        Ok(Handshake {
+            reserved: bytes[20..28].try_into().unwrap(),
+            info_hash: bytes[28..48].try_into().unwrap(),
+            peer_id: bytes[48..].try_into().unwrap(),
+        })
+    }
+
+    /// Sends our handshake and reads the peer's, which must be for the same torrent
+    pub fn exchange<S: Read + Write>(&self, stream: &mut S) -> Result<Handshake, HandshakeError> {
+        stream.write_all(&self.encode()).map_err(timeout_or_io)?;
+        let theirs = Handshake::read_from(stream)?;
+        if theirs.info_hash != self.info_hash {
+            return Err(HandshakeError::InfoHashMismatch(theirs.info_hash));
+        }
+        Ok(theirs)
+    }
+}
+
+/// Connects to `peer` and exchanges handshakes, giving up on the connection or on either
+/// side of the exchange after `timeout`. The stream keeps the timeout for later reads.
+pub fn connect(
+    peer: SocketAddr,
+    ours: &Handshake,
+    timeout: Duration,
+) -> Result<(TcpStream, Handshake), HandshakeError> {
+    let mut stream = TcpStream::connect_timeout(&peer, timeout).map_err(timeout_or_io)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let theirs = ours.exchange(&mut stream)?;
+    Ok((stream, theirs))
+}
+
+/// Sockets report an expired timeout as `WouldBlock` on Unix and `TimedOut` on Windows
+fn timeout_or_io(e: io::Error) -> HandshakeError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => HandshakeError::Timeout,
+        _ => HandshakeError::Io(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::scripted::ScriptedPeer;
+
+    fn peer(incoming: &[u8]) -> ScriptedPeer {
+        ScriptedPeer::new(incoming).chunked(7)
+    }
+
+    #[test]
+    fn exchanges_and_reads_reserved_bits() {
+        let ours = Handshake::new([1; 20], *b"00112233445566778899");
+        let mut reply = ours.encode().to_vec();
+        reply[20..28].copy_from_slice(&[0, 0, 0, 0, 0, 0x10, 0, 0x05]);
+        reply[48..].copy_from_slice(b"-XX0001-abcdefghijkl");
+        // A bitfield right behind the handshake must be left unread
+        reply.extend([0, 0, 0, 2, 5, 0xff]);
+        let mut peer = peer(&reply);
+
+        let theirs = ours.exchange(&mut peer).unwrap();
+        assert_eq!(peer.outgoing, ours.encode());
+        assert_eq!(&peer.outgoing[..20], b"\x13BitTorrent protocol");
+        assert_eq!(&theirs.peer_id, b"-XX0001-abcdefghijkl");
+        assert!(theirs.supports_dht() && theirs.supports_fast());
+        assert!(theirs.supports_extension_protocol());
+        assert!(ours.supports_extension_protocol() && !ours.supports_dht());
+        assert_eq!(peer.incoming.position(), HANDSHAKE_LENGTH as u64);
+    }
+
+    #[test]
+    fn rejects_bad_handshakes() {
+        let ours = Handshake::new([1; 20], [0; 20]);
+        let other_torrent = Handshake::new([2; 20], [0; 20]).encode();
+        assert!(matches!(
+            ours.exchange(&mut peer(&other_torrent)),
+            Err(HandshakeError::InfoHashMismatch(hash)) if hash == [2; 20]
+        ));
+
+        let mut wrong_protocol = ours.encode();
+        wrong_protocol[1..20].copy_from_slice(b"BitTorrent protocoX");
+        assert!(matches!(
+            ours.exchange(&mut peer(&wrong_protocol)),
+            Err(HandshakeError::BadProtocol(_))
+        ));
+
+        // Fewer than 68 bytes before the peer hangs up
+        assert!(matches!(
+            ours.exchange(&mut peer(&ours.encode()[..30])),
+            Err(HandshakeError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
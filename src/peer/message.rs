@@ -0,0 +1,368 @@
+//! Peer wire messages (BEP 3): each is a 4-byte big-endian length followed by an id byte and
+//! a payload; a zero length is a keep-alive
+
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+/// Longer messages than this are refused rather than allocated: a 16 KiB block plus headers
+/// is the most a well-behaved peer sends, bitfields of huge torrents aside
+pub const MAX_MESSAGE_LENGTH: usize = 1 << 20;
+
+const CHOKE: u8 = 0;
+const UNCHOKE: u8 = 1;
+const INTERESTED: u8 = 2;
+const NOT_INTERESTED: u8 = 3;
+const HAVE: u8 = 4;
+const BITFIELD: u8 = 5;
+const REQUEST: u8 = 6;
+const PIECE: u8 = 7;
+const CANCEL: u8 = 8;
+const PORT: u8 = 9;
+/// Message id of every BEP 10 extension message
+pub const EXTENDED: u8 = 20;
+
+#[derive(Debug, Error)]
+pub enum MessageError {
+    #[error("connection failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("peer sent a {0} byte message")]
+    TooLong(usize),
+    #[error("message {id} can't have a {length} byte payload")]
+    BadLength { id: u8, length: usize },
+    #[error("unknown message id {0}")]
+    UnknownId(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have(u32),
+    /// One bit per piece, high bit of the first byte first
+    Bitfield(Vec<u8>),
+    Request {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    Piece {
+        index: u32,
+        begin: u32,
+        block: Vec<u8>,
+    },
+    Cancel {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    /// The peer's DHT port (BEP 5)
+    Port(u16),
+    /// A BEP 10 message; `id` 0 is the extension handshake
+    Extended {
+        id: u8,
+        payload: Vec<u8>,
+    },
+}
+
+impl Message {
+    /// The message's bytes on the wire, length prefix included
+    pub fn encode(&self) -> Vec<u8> {
+        let (id, payload) = match self {
+            Message::KeepAlive => return vec![0; 4],
+            Message::Choke => (CHOKE, Vec::new()),
+            Message::Unchoke => (UNCHOKE, Vec::new()),
+            Message::Interested => (INTERESTED, Vec::new()),
+            Message::NotInterested => (NOT_INTERESTED, Vec::new()),
+            Message::Have(index) => (HAVE, index.to_be_bytes().to_vec()),
+            Message::Bitfield(bits) => (BITFIELD, bits.clone()),
+            Message::Request {
+                index,
+                begin,
+                length,
+            } => (
+                REQUEST,
+                [index, begin, length].map(|n| n.to_be_bytes()).concat(),
+            ),
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => (
+                PIECE,
+                [&index.to_be_bytes()[..], &begin.to_be_bytes(), block].concat(),
+            ),
+            Message::Cancel {
+                index,
+                begin,
+                length,
+            } => (
+                CANCEL,
+                [index, begin, length].map(|n| n.to_be_bytes()).concat(),
+            ),
+            Message::Port(port) => (PORT, port.to_be_bytes().to_vec()),
+            Message::Extended { id, payload } => (EXTENDED, [&[*id][..], payload].concat()),
+        };
+        let mut frame = Vec::with_capacity(5 + payload.len());
+        frame.extend((payload.len() as u32 + 1).to_be_bytes());
+        frame.push(id);
+        frame.extend(payload);
+        frame
+    }
+
+    /// Decodes the body of one frame, everything after the length prefix
+    pub fn decode(body: &[u8]) -> Result<Message, MessageError> {
+        let Some((&id, payload)) = body.split_first() else {
+            return Ok(Message::KeepAlive);
+        };
+        let bad_length = || MessageError::BadLength {
+            id,
+            length: payload.len(),
+        };
+        let fixed = |length: usize| match payload.len() == length {
+            true => Ok(()),
+            false => Err(bad_length()),
+        };
+        let u32_at =
+            |offset: usize| u32::from_be_bytes(payload[offset..offset + 4].try_into().unwrap());
+        Ok(match id {
+            CHOKE => fixed(0).map(|_| Message::Choke)?,
+            UNCHOKE => fixed(0).map(|_| Message::Unchoke)?,
+            INTERESTED => fixed(0).map(|_| Message::Interested)?,
+            NOT_INTERESTED => fixed(0).map(|_| Message::NotInterested)?,
+            HAVE => fixed(4).map(|_| Message::Have(u32_at(0)))?,
+            BITFIELD => Message::Bitfield(payload.to_vec()),
+            REQUEST | CANCEL => {
+                fixed(12)?;
+                let (index, begin, length) = (u32_at(0), u32_at(4), u32_at(8));
+                match id {
+                    REQUEST => Message::Request {
+                        index,
+                        begin,
+                        length,
+                    },
+                    _ => Message::Cancel {
+                        index,
+                        begin,
+                        length,
+                    },
+                }
+            }
+            PIECE if payload.len() >= 8 => Message::Piece {
+                index: u32_at(0),
+                begin: u32_at(4),
+                block: payload[8..].to_vec(),
+            },
+            PIECE => return Err(bad_length()),
+            PORT => {
+                fixed(2).map(|_| Message::Port(u16::from_be_bytes([payload[0], payload[1]])))?
+            }
+            EXTENDED => match payload.split_first() {
+                Some((&id, payload)) => Message::Extended {
+                    id,
+                    payload: payload.to_vec(),
+                },
+                None => return Err(bad_length()),
+            },
+            _ => return Err(MessageError::UnknownId(id)),
+        })
+    }
+
+    /// Reads exactly one message, blocking until all of it has arrived
+    pub fn read_from(reader: &mut impl Read) -> Result<Message, MessageError> {
+        let mut length = [0; 4];
+        reader.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_MESSAGE_LENGTH {
+            return Err(MessageError::TooLong(length));
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        Message::decode(&body)
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.encode())
+    }
+}
+
+/// Splits a byte stream into messages whatever the chunking: bytes can be fed as they
+/// arrive, a frame split across reads waits for its remainder and several frames in one read
+/// come out one at a time
+#[derive(Debug, Default)]
+pub struct MessageDecoder {
+    buffer: Vec<u8>,
+    /// What is still to come of an oversized frame, dropped as it arrives
+    skip: usize,
+}
+
+impl MessageDecoder {
+    pub fn new() -> MessageDecoder {
+        MessageDecoder::default()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let skipped = self.skip.min(bytes.len());
+        self.skip -= skipped;
+        self.buffer.extend_from_slice(&bytes[skipped..]);
+    }
+
+    /// Bytes fed but not yet returned as part of a message
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns the next complete message, or `None` until enough bytes have been fed. A
+    /// malformed frame is dropped from the buffer, so decoding can go on after the error; so is
+    /// a frame over [`MAX_MESSAGE_LENGTH`], whose remaining bytes are discarded as they are fed
+    /// rather than buffered.
+    pub fn decode(&mut self) -> Result<Option<Message>, MessageError> {
+        let Some(prefix) = self.buffer.first_chunk::<4>() else {
+            return Ok(None);
+        };
+        let length = u32::from_be_bytes(*prefix) as usize;
+        if length > MAX_MESSAGE_LENGTH {
+            self.buffer.drain(..4);
+            let buffered = length.min(self.buffer.len());
+            self.buffer.drain(..buffered);
+            self.skip = length - buffered;
+            return Err(MessageError::TooLong(length));
+        }
+        if self.buffer.len() < 4 + length {
+            return Ok(None);
+        }
+        let message = Message::decode(&self.buffer[4..4 + length]);
+        self.buffer.drain(..4 + length);
+        message.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixtures() -> Vec<(Message, Vec<u8>)> {
+        vec![
+            (Message::KeepAlive, vec![0, 0, 0, 0]),
+            (Message::Choke, vec![0, 0, 0, 1, 0]),
+            (Message::Unchoke, vec![0, 0, 0, 1, 1]),
+            (Message::Interested, vec![0, 0, 0, 1, 2]),
+            (Message::NotInterested, vec![0, 0, 0, 1, 3]),
+            (Message::Have(0x0102_0304), vec![0, 0, 0, 5, 4, 1, 2, 3, 4]),
+            (
+                Message::Bitfield(vec![0b1010_0000, 0xff]),
+                vec![0, 0, 0, 3, 5, 0b1010_0000, 0xff],
+            ),
+            (
+                Message::Request {
+                    index: 1,
+                    begin: 0x4000,
+                    length: 0x4000,
+                },
+                vec![0, 0, 0, 13, 6, 0, 0, 0, 1, 0, 0, 0x40, 0, 0, 0, 0x40, 0],
+            ),
+            (
+                Message::Piece {
+                    index: 2,
+                    begin: 8,
+                    block: b"abc".to_vec(),
+                },
+                vec![0, 0, 0, 12, 7, 0, 0, 0, 2, 0, 0, 0, 8, b'a', b'b', b'c'],
+            ),
+            (
+                Message::Cancel {
+                    index: 1,
+                    begin: 0,
+                    length: 5,
+                },
+                vec![0, 0, 0, 13, 8, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 5],
+            ),
+            (Message::Port(6881), vec![0, 0, 0, 3, 9, 0x1a, 0xe1]),
+            (
+                Message::Extended {
+                    id: 0,
+                    payload: b"de".to_vec(),
+                },
+                vec![0, 0, 0, 4, 20, 0, b'd', b'e'],
+            ),
+        ]
+    }
+
+    #[test]
+    fn encodes_and_decodes_fixtures() {
+        for (message, bytes) in fixtures() {
+            assert_eq!(message.encode(), bytes, "{:?}", message);
+            assert_eq!(Message::decode(&bytes[4..]).unwrap(), message);
+            assert_eq!(Message::read_from(&mut &bytes[..]).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn decoder_handles_split_and_coalesced_frames() {
+        let (messages, stream): (Vec<_>, Vec<_>) = fixtures().into_iter().unzip();
+        let stream = stream.concat();
+        for chunk_size in [1, 3, 7, stream.len()] {
+            let mut decoder = MessageDecoder::new();
+            let mut decoded = Vec::new();
+            for chunk in stream.chunks(chunk_size) {
+                decoder.feed(chunk);
+                while let Some(message) = decoder.decode().unwrap() {
+                    decoded.push(message);
+                }
+            }
+            assert_eq!(decoded, messages, "chunks of {}", chunk_size);
+            assert_eq!(decoder.buffered(), 0);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        assert!(matches!(
+            Message::decode(&[4, 0, 0]),
+            Err(MessageError::BadLength { id: 4, length: 2 })
+        ));
+        assert!(matches!(
+            Message::decode(&[1, 0]),
+            Err(MessageError::BadLength { id: 1, length: 1 })
+        ));
+        assert!(matches!(
+            Message::decode(&[7, 0, 0, 0]),
+            Err(MessageError::BadLength { id: 7, .. })
+        ));
+        assert!(matches!(
+            Message::decode(&[42]),
+            Err(MessageError::UnknownId(42))
+        ));
+        assert!(matches!(
+            Message::read_from(&mut &[0xff, 0, 0, 0][..]),
+            Err(MessageError::TooLong(_))
+        ));
+        assert!(matches!(
+            Message::read_from(&mut &[0, 0, 0, 5, 4, 0][..]),
+            Err(MessageError::Io(_))
+        ));
+
+        // A bad frame doesn't derail the frames after it
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&[0, 0, 0, 2, 0, 0, 0, 0, 0, 1, 1]);
+        assert!(decoder.decode().is_err());
+        assert_eq!(decoder.decode().unwrap(), Some(Message::Unchoke));
+
+        // Nor does one that is too long, however its body is split
+        let length = MAX_MESSAGE_LENGTH + 10;
+        let mut stream = (length as u32).to_be_bytes().to_vec();
+        stream.extend(vec![7; length]);
+        stream.extend(Message::Have(9).encode());
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&stream[..100]);
+        assert!(matches!(decoder.decode(), Err(MessageError::TooLong(l)) if l == length));
+        assert_eq!(decoder.decode().unwrap(), None);
+        for chunk in stream[100..].chunks(4096) {
+            decoder.feed(chunk);
+        }
+        assert_eq!(decoder.decode().unwrap(), Some(Message::Have(9)));
+        assert_eq!(decoder.buffered(), 0);
+    }
+}
@@ -0,0 +1,231 @@
+//! Fetching a torrent's info dictionary from a peer: the `ut_metadata` extension (BEP 9)
+//! negotiated through the extension protocol (BEP 10)
+
+use super::message::{Message, MessageError};
+use crate::bencode::{decode_bencoded_structure, decode_prefix, encode_bencoded_structure};
+use crate::bencode::{BValue, DecodeError, Progress};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+/// The id we ask peers to use for `ut_metadata` messages sent to us
+pub const LOCAL_UT_METADATA_ID: u8 = 1;
+/// Metadata is exchanged in pieces of this size; only the last may be shorter
+pub const METADATA_PIECE_SIZE: usize = 16 * 1024;
+/// Larger metadata than this is refused before anything is requested
+const MAX_METADATA_SIZE: usize = 16 * 1024 * 1024;
+
+const REQUEST: i64 = 0;
+const DATA: i64 = 1;
+const REJECT: i64 = 2;
+
+#[derive(Debug, Error)]
+pub enum MetadataError {
+    #[error("connection failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Message(#[from] MessageError),
+    #[error("peer sent invalid bencode: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("peer doesn't support ut_metadata")]
+    Unsupported,
+    #[error("peer didn't say how large the metadata is")]
+    MissingSize,
+    #[error("metadata size {0} is out of range")]
+    BadSize(usize),
+    #[error("peer rejected the request for metadata piece {0}")]
+    Rejected(usize),
+    #[error("malformed ut_metadata message: {0}")]
+    Malformed(&'static str),
+    #[error("metadata doesn't match the info hash")]
+    HashMismatch,
+}
+
+/// What a peer told us in its extension handshake
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtensionHandshake {
+    /// The id the peer wants `ut_metadata` messages sent with
+    pub ut_metadata: Option<u8>,
+    pub metadata_size: Option<usize>,
+    /// `v`, the peer's client name and version
+    pub client: Option<String>,
+}
+
+impl ExtensionHandshake {
+    fn from_payload(payload: &[u8]) -> Result<ExtensionHandshake, MetadataError> {
+        let value = decode_bencoded_structure(payload)?;
+        Ok(ExtensionHandshake {
+            ut_metadata: value
+                .get("m")
+                .and_then(|m| m.get("ut_metadata"))
+                .and_then(BValue::as_int)
+                .and_then(|id| u8::try_from(id).ok())
+                .filter(|&id| id != 0),
+            metadata_size: value
+                .get("metadata_size")
+                .and_then(BValue::as_int)
+                .and_then(|size| usize::try_from(size).ok()),
+            client: value.get("v").and_then(BValue::as_str).map(str::to_string),
+        })
+    }
+}
+
+fn dict(entries: &[(&str, BValue)]) -> BValue {
+    BValue::Dict(
+        entries
+            .iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value.clone()))
+            .collect::<BTreeMap<_, _>>(),
+    )
+}
+
+fn write_extended(stream: &mut impl Write, extension_id: u8, message: &BValue) -> io::Result<()> {
+    let message = Message::Extended {
+        id: extension_id,
+        payload: encode_bencoded_structure(message),
+    };
+    message.write_to(stream)
+}
+
+/// Sends our extension handshake and waits for the peer's, skipping any other messages
+/// (such as its bitfield) that arrive first. The peer must have set the extension protocol
+/// bit in its handshake.
+pub fn extension_handshake<S: Read + Write>(
+    stream: &mut S,
+) -> Result<ExtensionHandshake, MetadataError> {
+    let supported = dict(&[("ut_metadata", BValue::Int(LOCAL_UT_METADATA_ID as i64))]);
+    write_extended(stream, 0, &dict(&[("m", supported)]))?;
+    loop {
+        if let Message::Extended { id: 0, payload } = Message::read_from(stream)? {
+            return ExtensionHandshake::from_payload(&payload);
+        }
+    }
+}
+
+/// Requests every metadata piece, reassembles them and returns the info dictionary once
+/// `is_valid` accepts it, which should check it against the info hash
+pub fn fetch_metadata<S: Read + Write>(
+    stream: &mut S,
+    handshake: &ExtensionHandshake,
+    is_valid: impl Fn(&[u8]) -> bool,
+) -> Result<Vec<u8>, MetadataError> {
+    let peer_id = handshake.ut_metadata.ok_or(MetadataError::Unsupported)?;
+    let size = handshake.metadata_size.ok_or(MetadataError::MissingSize)?;
+    if size == 0 || size > MAX_METADATA_SIZE {
+        return Err(MetadataError::BadSize(size));
+    }
+    let piece_count = size.div_ceil(METADATA_PIECE_SIZE);
+    for piece in 0..piece_count {
+        let request = dict(&[
+            ("msg_type", BValue::Int(REQUEST)),
+            ("piece", BValue::Int(piece as i64)),
+        ]);
+        write_extended(stream, peer_id, &request)?;
+    }
+
+    let mut metadata = vec![0; size];
+    let mut received = vec![false; piece_count];
+    while received.contains(&false) {
+        let payload = match Message::read_from(stream)? {
+            Message::Extended { id, payload } if id == LOCAL_UT_METADATA_ID => payload,
+            _ => continue,
+        };
+        // The bencoded header is followed directly by the piece's raw bytes
+        let (header, header_length) = match decode_prefix(&payload)? {
+            Progress::Complete(header, consumed) => (header, consumed),
+            Progress::NeedMore(_) => return Err(MetadataError::Malformed("truncated header")),
+        };
+        let piece = header
+            .get("piece")
+            .and_then(BValue::as_int)
+            .and_then(|piece| usize::try_from(piece).ok())
+            .filter(|&piece| piece < piece_count)
+            .ok_or(MetadataError::Malformed("bad piece index"))?;
+        match header.get("msg_type").and_then(BValue::as_int) {
+            Some(DATA) => {}
+            Some(REJECT) => return Err(MetadataError::Rejected(piece)),
+            _ => continue,
+        }
+        let data = &payload[header_length..];
+        let start = piece * METADATA_PIECE_SIZE;
+        let end = (start + METADATA_PIECE_SIZE).min(size);
+        if data.len() != end - start {
+            return Err(MetadataError::Malformed("piece has the wrong length"));
+        }
+        metadata[start..end].copy_from_slice(data);
+        received[piece] = true;
+    }
+
+    if !is_valid(&metadata) {
+        return Err(MetadataError::HashMismatch);
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::scripted::ScriptedPeer;
+    use std::io::Cursor;
+
+    fn data_message(piece: usize, total_size: usize, data: &[u8]) -> Vec<u8> {
+        let header = dict(&[
+            ("msg_type", BValue::Int(DATA)),
+            ("piece", BValue::Int(piece as i64)),
+            ("total_size", BValue::Int(total_size as i64)),
+        ]);
+        let mut payload = encode_bencoded_structure(&header);
+        payload.extend(data);
+        Message::Extended {
+            id: LOCAL_UT_METADATA_ID,
+            payload,
+        }
+        .encode()
+    }
+
+    #[test]
+    fn reassembles_metadata_pieces_in_any_order() {
+        let metadata: Vec<u8> = (0..METADATA_PIECE_SIZE + 100).map(|i| i as u8).collect();
+        let mut incoming = Vec::new();
+        // A bitfield first, which the handshake has to skip
+        incoming.extend(Message::Bitfield(vec![0xff]).encode());
+        let peer_handshake = b"d1:md11:ut_metadatai3ee13:metadata_sizei16484e1:v4:teste";
+        let handshake_message = Message::Extended {
+            id: 0,
+            payload: peer_handshake.to_vec(),
+        };
+        incoming.extend(handshake_message.encode());
+        incoming.extend(data_message(
+            1,
+            metadata.len(),
+            &metadata[METADATA_PIECE_SIZE..],
+        ));
+        incoming.extend(data_message(
+            0,
+            metadata.len(),
+            &metadata[..METADATA_PIECE_SIZE],
+        ));
+
+        let mut peer = ScriptedPeer::new(&incoming);
+        let handshake = extension_handshake(&mut peer).unwrap();
+        assert_eq!(handshake.ut_metadata, Some(3));
+        assert_eq!(handshake.metadata_size, Some(metadata.len()));
+        assert_eq!(handshake.client.as_deref(), Some("test"));
+
+        let fetched = fetch_metadata(&mut peer, &handshake, |_| true).unwrap();
+        assert_eq!(fetched, metadata);
+        let first_request = &peer.outgoing[peer.outgoing.len() - 2 * 31..][..31];
+        assert_eq!(&first_request[4..6], &[20, 3]);
+        assert_eq!(&first_request[6..], b"d8:msg_typei0e5:piecei0ee");
+
+        peer.incoming = Cursor::new(data_message(0, 10, &[0; 10]));
+        let small = ExtensionHandshake {
+            metadata_size: Some(10),
+            ..handshake
+        };
+        assert!(matches!(
+            fetch_metadata(&mut peer, &small, |_| false),
+            Err(MetadataError::HashMismatch)
+        ));
+    }
+}
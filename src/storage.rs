@@ -0,0 +1,362 @@
+//! Reading and writing a torrent's payload on disk, piece by piece
+
+use crate::torrent::{FileSlice, Metainfo};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// What was found on disk for one piece
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PieceStatus {
+    Good,
+    Bad,
+    /// A file the piece covers is absent or too short
+    Missing,
+}
+
+/// Piece counts for one file. A piece spanning several files counts towards each of them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub length: u64,
+    pub good: usize,
+    pub bad: usize,
+    pub missing: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VerifyReport {
+    /// Status of every piece, by index
+    pub pieces: Vec<PieceStatus>,
+    /// Every file except pad files and symlinks
+    pub files: Vec<FileReport>,
+}
+
+impl VerifyReport {
+    pub fn count(&self, status: PieceStatus) -> usize {
+        self.pieces.iter().filter(|&&piece| piece == status).count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pieces.iter().all(|&piece| piece == PieceStatus::Good)
+    }
+}
+
+/// Creates every file of the torrent under `root` at its final size and applies its BEP 47
+/// attributes: symlinks are linked instead of created, `x` sets the executable bits and `h`
+/// marks the file hidden where the platform has such a flag. Pad files are never created.
+pub fn prepare_files(metainfo: &Metainfo, root: &Path) -> io::Result<()> {
+    for file in metainfo.files() {
+        if file.attributes.padding {
+            continue;
+        }
+        let path = root.join(&file.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let (true, Some(target)) = (file.attributes.symlink, &file.symlink_target) {
+            if fs::symlink_metadata(&path).is_ok() {
+                fs::remove_file(&path)?;
+            }
+            create_symlink(&relative_target(file, target), &path)?;
+            continue;
+        }
+        open_for_writing(&path, file.attributes.hidden)?.set_len(file.length)?;
+        if file.attributes.executable {
+            set_executable(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes piece `index` into the files it covers. Bytes falling in pad files are dropped.
+pub fn write_piece(metainfo: &Metainfo, root: &Path, index: usize, data: &[u8]) -> io::Result<()> {
+    if data.len() as u64 != metainfo.piece_size(index) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "piece {} is {} bytes, expected {}",
+                index,
+                data.len(),
+                metainfo.piece_size(index)
+            ),
+        ));
+    }
+    let files = metainfo.files();
+    for span in metainfo.piece_spans(index) {
+        let file = &files[span.file_index];
+        if file.attributes.padding || file.attributes.symlink {
+            continue;
+        }
+        let mut handle = open_for_writing(&root.join(&file.path), file.attributes.hidden)?;
+        handle.seek(SeekFrom::Start(span.file_range.start))?;
+        handle.write_all(&data[span.piece_range])?;
+    }
+    Ok(())
+}
+
+/// Reads piece `index` back from the files under `root`, with zeros for pad files
+pub fn read_piece(metainfo: &Metainfo, root: &Path, index: usize) -> io::Result<Vec<u8>> {
+    let mut piece = vec![0; metainfo.piece_size(index) as usize];
+    let files = metainfo.files();
+    for span in metainfo.piece_spans(index) {
+        let file = &files[span.file_index];
+        if file.attributes.padding || file.attributes.symlink {
+            continue;
+        }
+        let mut handle = File::open(root.join(&file.path))?;
+        handle.seek(SeekFrom::Start(span.file_range.start))?;
+        handle.read_exact(&mut piece[span.piece_range])?;
+    }
+    Ok(piece)
+}
+
+/// Runs `f` on every piece index on up to `threads` threads (by default the available
+/// parallelism) and returns the results in piece order. Each thread gets a contiguous run of
+/// pieces to keep reads sequential.
+pub fn map_pieces<T: Send>(
+    metainfo: &Metainfo,
+    threads: Option<usize>,
+    f: impl Fn(usize) -> io::Result<T> + Sync,
+) -> io::Result<Vec<T>> {
+    let threads = threads
+        .or_else(|| thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1)
+        .max(1);
+    let piece_count = metainfo.piece_count();
+    let per_thread = piece_count.div_ceil(threads).max(1);
+    let f = &f;
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..piece_count)
+            .step_by(per_thread)
+            .map(|start| {
+                scope.spawn(move || -> io::Result<Vec<T>> {
+                    (start..(start + per_thread).min(piece_count))
+                        .map(f)
+                        .collect()
+                })
+            })
+            .collect();
+        let mut results = Vec::with_capacity(piece_count);
+        for worker in workers {
+            results.extend(worker.join().expect("piece thread panicked")?);
+        }
+        Ok(results)
+    })
+}
+
+/// Hashes the payload under `root` piece by piece against the torrent's v1 piece hashes
+pub fn verify(
+    metainfo: &Metainfo,
+    root: &Path,
+    threads: Option<usize>,
+) -> io::Result<VerifyReport> {
+    if !metainfo.info().has_v1() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the torrent has no v1 piece hashes",
+        ));
+    }
+    let hashes: Vec<&[u8]> = metainfo.piece_hashes().collect();
+    let pieces = map_pieces(metainfo, threads, |index| {
+        let missing = |e: &io::Error| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::UnexpectedEof
+            )
+        };
+        Ok(match read_piece(metainfo, root, index) {
+            Ok(piece) if Sha1::digest(&piece)[..] == *hashes[index] => PieceStatus::Good,
+            Ok(_) => PieceStatus::Bad,
+            Err(e) if missing(&e) => PieceStatus::Missing,
+            Err(e) => return Err(e),
+        })
+    })?;
+
+    let files = metainfo
+        .files()
+        .iter()
+        .enumerate()
+        .filter(|(_, file)| !file.attributes.padding && !file.attributes.symlink)
+        .map(|(file_index, file)| {
+            let statuses = &pieces[metainfo.file_pieces(file_index)];
+            let count = |status| statuses.iter().filter(|&&piece| piece == status).count();
+            FileReport {
+                good: count(PieceStatus::Good),
+                bad: count(PieceStatus::Bad),
+                missing: count(PieceStatus::Missing),
+                path: file.path.clone(),
+                length: file.length,
+            }
+        })
+        .collect();
+    Ok(VerifyReport { pieces, files })
+}
+
+/// `symlink path` is relative to the torrent's directory, but a link's target is resolved
+/// from the directory holding the link
+fn relative_target(file: &FileSlice, target: &Path) -> PathBuf {
+    let depth = file.path.components().count().saturating_sub(2);
+    let mut relative: PathBuf = std::iter::repeat_n("..", depth).collect();
+    relative.push(target);
+    relative
+}
+
+#[cfg_attr(not(windows), allow(unused_variables))]
+fn open_for_writing(path: &Path, hidden: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(false);
+    #[cfg(windows)]
+    if hidden {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        options.attributes(FILE_ATTRIBUTE_HIDDEN);
+    }
+    options.open(path)
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = fs::metadata(path)?.permissions();
+    // Grant execute to whoever may read the file
+    permissions.set_mode(permissions.mode() | (permissions.mode() & 0o444) >> 2);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks aren't supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::BValue;
+    use std::collections::BTreeMap;
+
+    fn file(path: &[&str], length: i64, attr: &str) -> BValue {
+        let mut entry = BTreeMap::from([
+            (b"length".to_vec(), BValue::Int(length)),
+            (
+                b"path".to_vec(),
+                BValue::List(
+                    path.iter()
+                        .map(|part| BValue::Bytes(part.as_bytes().to_vec()))
+                        .collect(),
+                ),
+            ),
+        ]);
+        if !attr.is_empty() {
+            entry.insert(b"attr".to_vec(), BValue::Bytes(attr.as_bytes().to_vec()));
+        }
+        if attr.contains('l') {
+            entry.insert(
+                b"symlink path".to_vec(),
+                BValue::List(vec![BValue::Bytes(b"a".to_vec())]),
+            );
+        }
+        BValue::Dict(entry)
+    }
+
+    #[test]
+    fn skips_pad_files_and_applies_attributes() {
+        let files = vec![
+            file(&["a"], 5, ""),
+            file(&[".pad", "3"], 3, "p"),
+            file(&["bin", "run"], 6, "x"),
+            file(&["bin", "link"], 0, "l"),
+        ];
+        let info = BTreeMap::from([
+            (b"files".to_vec(), BValue::List(files)),
+            (b"name".to_vec(), BValue::Bytes(b"t".to_vec())),
+            (b"piece length".to_vec(), BValue::Int(8)),
+            (b"pieces".to_vec(), BValue::Bytes(vec![0; 40])),
+        ]);
+        let torrent = BValue::Dict(BTreeMap::from([(b"info".to_vec(), BValue::Dict(info))]));
+        let metainfo =
+            Metainfo::from_bytes(&crate::bencode::encode_bencoded_structure(&torrent)).unwrap();
+        assert_eq!(metainfo.total_length(), 11);
+
+        let root = tempfile::tempdir().unwrap();
+        prepare_files(&metainfo, root.path()).unwrap();
+        write_piece(&metainfo, root.path(), 0, b"hello\0\0\0").unwrap();
+        write_piece(&metainfo, root.path(), 1, b"script").unwrap();
+
+        assert_eq!(fs::read(root.path().join("t/a")).unwrap(), b"hello");
+        assert_eq!(fs::read(root.path().join("t/bin/run")).unwrap(), b"script");
+        assert!(!root.path().join("t/.pad").exists());
+        assert_eq!(
+            read_piece(&metainfo, root.path(), 0).unwrap(),
+            b"hello\0\0\0"
+        );
+        assert!(write_piece(&metainfo, root.path(), 1, b"short").is_err());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(root.path().join("t/bin/run"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_ne!(mode & 0o100, 0);
+            let link = root.path().join("t/bin/link");
+            assert_eq!(fs::read_link(&link).unwrap(), Path::new("../a"));
+            assert_eq!(fs::read(link).unwrap(), b"hello");
+        }
+    }
+
+    #[test]
+    fn verify_reports_pieces_per_file() {
+        let root = tempfile::tempdir().unwrap();
+        let payload = root.path().join("v");
+        fs::create_dir_all(&payload).unwrap();
+        fs::write(payload.join("a"), vec![1; 20]).unwrap();
+        fs::write(payload.join("b"), vec![2; 20]).unwrap();
+        fs::write(payload.join("c"), vec![3; 10]).unwrap();
+        let options = crate::torrent::create::CreateOptions {
+            piece_length: Some(16),
+            ..Default::default()
+        };
+        let metainfo = crate::torrent::create::create(&payload, &options).unwrap();
+        let report = verify(&metainfo, root.path(), Some(2)).unwrap();
+        assert!(report.is_complete());
+
+        // Piece 1 spans a and b, piece 3 is the short last piece in c alone
+        let mut corrupted = vec![2; 20];
+        corrupted[5] = 0;
+        fs::write(payload.join("b"), corrupted).unwrap();
+        fs::remove_file(payload.join("c")).unwrap();
+        let report = verify(&metainfo, root.path(), Some(2)).unwrap();
+        use PieceStatus::*;
+        assert_eq!(report.pieces, [Good, Bad, Missing, Missing]);
+        let counts: Vec<_> = report
+            .files
+            .iter()
+            .map(|file| (file.good, file.bad, file.missing))
+            .collect();
+        assert_eq!(counts, [(1, 1, 0), (0, 1, 1), (0, 0, 2)]);
+    }
+}
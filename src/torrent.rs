@@ -1,21 +1,722 @@
-use std::net::SocketAddrV4;
-/// Helper functions for processing torrent files
+//! Helper functions for processing torrent files
 
-use base64::{engine::general_purpose, Engine as _};
-use serde::{Deserialize, Serialize};
-use serde_json::{Map, Value};
+pub mod create;
+pub mod edit;
+pub mod merkle;
+
+use crate::bencode::{self, decode_with_info_span, encode_bencoded_structure, BValue, DecodeError};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_bytes::ByteBuf;
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::{fs, io};
+use thiserror::Error;
+
+/// A parsed `.torrent` file (BEP 3 plus the common `announce-list` and `url-list` extensions)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metainfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announce: Option<String>,
+    /// Tiers of tracker URLs (BEP 12)
+    #[serde(
+        rename = "announce-list",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub announce_list: Vec<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(
+        rename = "created by",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created_by: Option<String>,
+    /// Seconds since the Unix epoch
+    #[serde(
+        rename = "creation date",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub creation_date: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Web seeds (BEP 19). Torrents use either a single string or a list.
+    #[serde(
+        rename = "url-list",
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub url_list: Vec<String>,
+    /// Read with [`Metainfo::info`]; changed through [`Metainfo::info_mut`] so the file layout
+    /// follows
+    info: Info,
+    /// v2 piece hashes of every file longer than a piece, keyed by its `pieces root` (BEP 52)
+    #[serde(
+        rename = "piece layers",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub piece_layers: BTreeMap<ByteBuf, ByteBuf>,
+    /// SHA-1 over the raw `info` bytes, filled in by [`Metainfo::from_bytes`]
+    #[serde(skip)]
+    pub info_hash: [u8; 20],
+    /// SHA-256 over the raw `info` bytes, for v2 and hybrid torrents
+    #[serde(skip)]
+    pub info_hash_v2: Option<[u8; 32]>,
+    #[serde(skip)]
+    layout: Layout,
+}
+
+/// [`Metainfo::files`], worked out from `info` the first time it's needed so piece lookups
+/// don't rebuild it. Cleared whenever `info` is borrowed mutably.
+#[derive(Debug, Clone, Default)]
+struct Layout(OnceLock<Vec<FileSlice>>);
+
+/// A cache of values derived from `info`, so it never makes two torrents unequal
+impl PartialEq for Layout {
+    fn eq(&self, _: &Layout) -> bool {
+        true
+    }
+}
 
-pub struct TorrentInfo(pub String, pub String, pub i64);
+impl Eq for Layout {}
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
-struct Info {
-    length: i64,
-    name: String,
+/// Serialized by hand: `pieces` depends on whether the torrent has v1 data at all
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Info {
+    pub name: String,
     #[serde(rename = "piece length")]
-    piece_length: i64,
-    #[serde(with = "serde_bytes")]
-    pieces: Vec<u8>,
+    pub piece_length: u64,
+    /// Concatenated 20-byte SHA-1 hashes, one per piece; absent from v2-only torrents
+    #[serde(default, with = "serde_bytes")]
+    pub pieces: Vec<u8>,
+    /// Size of the file in single-file mode
+    #[serde(default)]
+    pub length: Option<u64>,
+    /// The files in multi-file mode, in the order their bytes appear in the pieces
+    #[serde(default)]
+    pub files: Vec<FileEntry>,
+    #[serde(default)]
+    pub md5sum: Option<String>,
+    /// Private torrents (BEP 27) must only get peers from their trackers
+    #[serde(default)]
+    pub private: Option<bool>,
+    #[serde(default)]
+    pub source: Option<String>,
+    /// BEP 47 attribute flags of the file in single-file mode
+    #[serde(default)]
+    pub attr: Option<String>,
+    /// 2 for v2 and hybrid torrents
+    #[serde(rename = "meta version", default)]
+    pub meta_version: Option<u64>,
+    /// The v2 directory structure, keyed by path component
+    #[serde(rename = "file tree", default)]
+    pub file_tree: BTreeMap<String, FileTreeNode>,
+}
+
+impl Serialize for Info {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut info = serializer.serialize_struct("Info", 11)?;
+        info.serialize_field("name", &self.name)?;
+        info.serialize_field("piece length", &self.piece_length)?;
+        // BEP 3 requires `pieces` even for an empty payload; only v2-only torrents leave it out
+        if self.has_v1() || !self.pieces.is_empty() {
+            info.serialize_field("pieces", serde_bytes::Bytes::new(&self.pieces))?;
+        }
+        if let Some(length) = self.length {
+            info.serialize_field("length", &length)?;
+        }
+        if !self.files.is_empty() {
+            info.serialize_field("files", &self.files)?;
+        }
+        for (key, value) in [
+            ("md5sum", &self.md5sum),
+            ("source", &self.source),
+            ("attr", &self.attr),
+        ] {
+            if let Some(value) = value {
+                info.serialize_field(key, value)?;
+            }
+        }
+        if let Some(private) = self.private {
+            info.serialize_field("private", &private)?;
+        }
+        if let Some(meta_version) = self.meta_version {
+            info.serialize_field("meta version", &meta_version)?;
+        }
+        if !self.file_tree.is_empty() {
+            info.serialize_field("file tree", &self.file_tree)?;
+        }
+        info.end()
+    }
+}
+
+/// A node of the v2 `file tree`: a file, marked by an empty-string key, or a directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FileTreeNode {
+    File {
+        #[serde(rename = "")]
+        file: TreeFile,
+    },
+    Directory(BTreeMap<String, FileTreeNode>),
+}
+
+/// A file in the v2 `file tree`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeFile {
+    pub length: u64,
+    /// Merkle root of the file's 16 KiB blocks; empty files have none
+    #[serde(
+        rename = "pieces root",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub pieces_root: Option<ByteBuf>,
+    /// BEP 47 attribute flags, mirroring the file's `info.files` entry in hybrid torrents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attr: Option<String>,
+    #[serde(
+        rename = "symlink path",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub symlink_path: Option<Vec<String>>,
+}
+
+/// Which hashing schemes a torrent carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TorrentVersion {
+    V1,
+    V2,
+    Hybrid,
+}
+
+impl fmt::Display for TorrentVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TorrentVersion::V1 => "v1",
+            TorrentVersion::V2 => "v2",
+            TorrentVersion::Hybrid => "hybrid",
+        })
+    }
+}
+
+/// One entry of `info.files`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub length: u64,
+    /// Path components below the torrent's directory; the last one is the file name
+    pub path: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5sum: Option<String>,
+    /// BEP 47 attribute flags, see [`FileAttributes`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attr: Option<String>,
+    /// Target of a symlink (`attr` contains `l`), relative to the torrent's directory
+    #[serde(
+        rename = "symlink path",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub symlink_path: Option<Vec<String>>,
+}
+
+/// The BEP 47 `attr` flags we act on. Unknown flags are ignored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    /// `p`: zeros aligning the next file to a piece boundary, never written to disk
+    pub padding: bool,
+    /// `x`
+    pub executable: bool,
+    /// `h`
+    pub hidden: bool,
+    /// `l`: the file is a symlink to its `symlink path`
+    pub symlink: bool,
+}
+
+impl FileAttributes {
+    pub fn parse(attr: Option<&str>) -> FileAttributes {
+        let attr = attr.unwrap_or_default();
+        FileAttributes {
+            padding: attr.contains('p'),
+            executable: attr.contains('x'),
+            hidden: attr.contains('h'),
+            symlink: attr.contains('l'),
+        }
+    }
+}
+
+/// A file of the torrent together with where its bytes sit in the concatenated payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSlice {
+    /// Relative path on disk, starting with the torrent's `name`
+    pub path: PathBuf,
+    pub length: u64,
+    /// Offset of the file's first byte in the payload
+    pub offset: u64,
+    pub attributes: FileAttributes,
+    /// Symlink target relative to the torrent's directory
+    pub symlink_target: Option<PathBuf>,
+}
+
+/// The part of one file covered by a piece
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PieceSpan {
+    /// Index into [`Metainfo::files`]
+    pub file_index: usize,
+    /// Byte range within the file
+    pub file_range: Range<u64>,
+    /// Byte range within the piece
+    pub piece_range: Range<usize>,
+}
+
+#[derive(Debug, Error)]
+pub enum MetainfoError {
+    #[error("couldn't read torrent file: {0}")]
+    Io(#[from] io::Error),
+    #[error("torrent file isn't valid bencode: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("torrent file has no info dictionary")]
+    MissingInfo,
+    #[error("invalid torrent metadata: {0}")]
+    Invalid(#[from] bencode::Error),
+    #[error("pieces is {0} bytes, which isn't a whole number of SHA-1 hashes")]
+    BadPieces(usize),
+    #[error("info has neither length nor files")]
+    MissingLength,
+    #[error("info has both length and files")]
+    AmbiguousLength,
+    #[error("unsafe file path in torrent: {0:?}")]
+    BadPath(Vec<String>),
+    #[error("pieces has {found} hashes but the payload needs {expected}")]
+    PieceCountMismatch { found: usize, expected: usize },
+    #[error("unsupported meta version {0}")]
+    UnsupportedVersion(u64),
+    #[error("piece length must be positive")]
+    ZeroPieceLength,
+    #[error("v2 torrent has no file tree")]
+    MissingFileTree,
+    #[error("v2 piece length {0} isn't a power of two of at least 16 KiB")]
+    BadPieceLength(u64),
+    #[error("{0} has no valid pieces root")]
+    BadPiecesRoot(PathBuf),
+    #[error("piece layer of {0} doesn't match its pieces root")]
+    BadPieceLayer(PathBuf),
+    #[error("hybrid torrent's v1 files don't match its file tree")]
+    HybridMismatch,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(url) => vec![url],
+        OneOrMany::Many(urls) => urls,
+    })
+}
+
+impl Metainfo {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Metainfo, MetainfoError> {
+        let (value, info_span) = decode_with_info_span(bytes)?;
+        let info_span = info_span.ok_or(MetainfoError::MissingInfo)?;
+        let mut metainfo: Metainfo = bencode::from_value(value)?;
+        metainfo.info_hash = Sha1::digest(&bytes[info_span.clone()]).into();
+        // Every file path starts with `name`, so it has to be a single safe component too
+        if !is_safe_path(std::slice::from_ref(&metainfo.info.name)) {
+            return Err(MetainfoError::BadPath(vec![metainfo.info.name]));
+        }
+
+        match metainfo.info.meta_version {
+            Some(2) => {
+                metainfo.info_hash_v2 = Some(Sha256::digest(&bytes[info_span]).into());
+                metainfo.check_file_tree()?;
+            }
+            Some(version) => return Err(MetainfoError::UnsupportedVersion(version)),
+            None => {}
+        }
+        if !metainfo.info.has_v1() {
+            return match metainfo.info.has_v2() {
+                true => Ok(metainfo),
+                false => Err(MetainfoError::MissingLength),
+            };
+        }
+
+        if metainfo.info.piece_length == 0 {
+            return Err(MetainfoError::ZeroPieceLength);
+        }
+        if !metainfo.info.pieces.len().is_multiple_of(20) {
+            return Err(MetainfoError::BadPieces(metainfo.info.pieces.len()));
+        }
+        if metainfo.info.length.is_some() && !metainfo.info.files.is_empty() {
+            return Err(MetainfoError::AmbiguousLength);
+        }
+        for file in &metainfo.info.files {
+            let paths = std::iter::once(&file.path).chain(&file.symlink_path);
+            if let Some(path) = paths.into_iter().find(|path| !is_safe_path(path)) {
+                return Err(MetainfoError::BadPath(path.clone()));
+            }
+        }
+        let expected = metainfo.piece_count();
+        if metainfo.info.pieces.len() / 20 != expected {
+            return Err(MetainfoError::PieceCountMismatch {
+                found: metainfo.info.pieces.len() / 20,
+                expected,
+            });
+        }
+        if metainfo.info.has_v2() && !metainfo.is_consistent_hybrid() {
+            return Err(MetainfoError::HybridMismatch);
+        }
+        Ok(metainfo)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Metainfo, MetainfoError> {
+        Metainfo::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn info(&self) -> &Info {
+        &self.info
+    }
+
+    /// Changes to `info` don't update `info_hash`, which stays the hash of the parsed bytes
+    pub fn info_mut(&mut self) -> &mut Info {
+        self.layout = Layout::default();
+        &mut self.info
+    }
+
+    pub fn info_hash_hex(&self) -> String {
+        hex::encode(self.info_hash)
+    }
+
+    pub fn info_hash_v2_hex(&self) -> Option<String> {
+        self.info_hash_v2.map(hex::encode)
+    }
+
+    /// The 20-byte hash identifying the swarm in tracker requests and handshakes: the v1
+    /// info hash, or the v2 info hash truncated to 20 bytes for v2-only torrents
+    pub fn swarm_hash(&self) -> [u8; 20] {
+        match (self.info.has_v1(), self.info_hash_v2) {
+            (false, Some(v2)) => v2[..20].try_into().expect("slice is 20 bytes"),
+            _ => self.info_hash,
+        }
+    }
+
+    /// Every hash the swarm may be found under; hybrid torrents are announced with both
+    pub fn swarm_hashes(&self) -> Vec<[u8; 20]> {
+        match (self.version(), self.info_hash_v2) {
+            (TorrentVersion::Hybrid, Some(v2)) => vec![
+                self.info_hash,
+                v2[..20].try_into().expect("slice is 20 bytes"),
+            ],
+            _ => vec![self.swarm_hash()],
+        }
+    }
+
+    pub fn version(&self) -> TorrentVersion {
+        match (self.info.has_v1(), self.info.has_v2()) {
+            (true, true) => TorrentVersion::Hybrid,
+            (false, true) => TorrentVersion::V2,
+            _ => TorrentVersion::V1,
+        }
+    }
+
+    /// The tracker to announce to: `announce`, or the first tracker in `announce-list`
+    pub fn tracker_url(&self) -> Option<&str> {
+        self.announce.as_deref().or_else(|| {
+            self.announce_list
+                .iter()
+                .flatten()
+                .next()
+                .map(String::as_str)
+        })
+    }
+
+    /// Bytes of file data, not counting pad files
+    pub fn total_length(&self) -> u64 {
+        match self.info.length {
+            Some(length) => length,
+            None => self
+                .files()
+                .iter()
+                .filter(|file| !file.attributes.padding)
+                .map(|file| file.length)
+                .sum(),
+        }
+    }
+
+    pub fn piece_hashes(&self) -> impl Iterator<Item = &[u8]> {
+        self.info.pieces.chunks(20)
+    }
+
+    pub fn is_multi_file(&self) -> bool {
+        match self.info.has_v1() {
+            true => self.info.length.is_none(),
+            false => !self.info.is_single_file_tree(),
+        }
+    }
+
+    /// Every file of the torrent with its place in the payload. A single-file torrent has one.
+    /// In v2-only torrents every file starts on a piece boundary, leaving gaps in the payload.
+    /// Pad files are included because they take up space in the pieces.
+    pub fn files(&self) -> &[FileSlice] {
+        self.layout.0.get_or_init(|| self.lay_out_files())
+    }
+
+    fn lay_out_files(&self) -> Vec<FileSlice> {
+        if let Some(length) = self.info.length {
+            return vec![FileSlice {
+                path: PathBuf::from(&self.info.name),
+                length,
+                offset: 0,
+                attributes: FileAttributes::parse(self.info.attr.as_deref()),
+                symlink_target: None,
+            }];
+        }
+        if !self.info.has_v1() {
+            return self.tree_file_slices();
+        }
+        let mut offset = 0;
+        self.info
+            .files
+            .iter()
+            .map(|file| {
+                let slice = FileSlice {
+                    path: file
+                        .path
+                        .iter()
+                        .fold(PathBuf::from(&self.info.name), |path, part| path.join(part)),
+                    length: file.length,
+                    offset,
+                    attributes: FileAttributes::parse(file.attr.as_deref()),
+                    symlink_target: file.symlink_path.as_deref().map(PathBuf::from_iter),
+                };
+                offset += file.length;
+                slice
+            })
+            .collect()
+    }
+
+    fn tree_file_slices(&self) -> Vec<FileSlice> {
+        let single_file = self.info.is_single_file_tree();
+        let mut offset = 0;
+        self.info
+            .tree_files()
+            .into_iter()
+            .map(|(path, file)| {
+                let slice = FileSlice {
+                    path: match single_file {
+                        true => PathBuf::from(&path[0]),
+                        false => path
+                            .iter()
+                            .fold(PathBuf::from(&self.info.name), |path, part| path.join(part)),
+                    },
+                    length: file.length,
+                    offset,
+                    attributes: FileAttributes::parse(file.attr.as_deref()),
+                    symlink_target: file.symlink_path.as_deref().map(PathBuf::from_iter),
+                };
+                offset += file.length.next_multiple_of(self.info.piece_length.max(1));
+                slice
+            })
+            .collect()
+    }
+
+    /// End of the last byte of file data in the piece layout
+    fn layout_length(&self) -> u64 {
+        // Files are in payload order, so the last one ends furthest in
+        self.files()
+            .last()
+            .map_or(0, |file| file.offset + file.length)
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.layout_length().div_ceil(self.info.piece_length.max(1)) as usize
+    }
+
+    /// Length of piece `index`. Only the last piece (and in v2, the last piece of each file)
+    /// can be shorter than `piece length`.
+    pub fn piece_size(&self, index: usize) -> u64 {
+        self.piece_spans(index)
+            .iter()
+            .map(|span| span.file_range.end - span.file_range.start)
+            .sum()
+    }
+
+    /// The file byte ranges that make up piece `index`, in payload order
+    pub fn piece_spans(&self, index: usize) -> Vec<PieceSpan> {
+        let piece_length = self.info.piece_length;
+        let Some((start, end)) = (index as u64)
+            .checked_mul(piece_length)
+            .and_then(|start| Some((start, start.checked_add(piece_length)?)))
+            .filter(|_| piece_length > 0)
+        else {
+            return Vec::new();
+        };
+        let files = self.files();
+        // File ends only grow along the payload, so the piece's first file can be searched for
+        let first = files.partition_point(|file| file.offset + file.length <= start);
+        files[first..]
+            .iter()
+            .take_while(|file| file.offset < end)
+            .enumerate()
+            .filter(|(_, file)| file.length > 0)
+            .map(|(position, file)| {
+                let file_index = first + position;
+                let from = start.max(file.offset);
+                let to = end.min(file.offset + file.length);
+                PieceSpan {
+                    file_index,
+                    file_range: from - file.offset..to - file.offset,
+                    piece_range: (from - start) as usize..(to - start) as usize,
+                }
+            })
+            .collect()
+    }
+
+    /// The pieces that hold at least one byte of file `file_index`
+    pub fn file_pieces(&self, file_index: usize) -> Range<usize> {
+        let file = &self.files()[file_index];
+        if file.length == 0 {
+            return 0..0;
+        }
+        let piece_length = self.info.piece_length.max(1);
+        (file.offset / piece_length) as usize
+            ..(file.offset + file.length).div_ceil(piece_length) as usize
+    }
+
+    /// Checks the v2 fields: piece length, safe paths, pieces roots and, where the torrent
+    /// carries them, that each file's piece layer hashes up to its pieces root
+    fn check_file_tree(&self) -> Result<(), MetainfoError> {
+        if self.info.file_tree.is_empty() {
+            return Err(MetainfoError::MissingFileTree);
+        }
+        let piece_length = self.info.piece_length;
+        if !piece_length.is_power_of_two() || piece_length < merkle::BLOCK_SIZE as u64 {
+            return Err(MetainfoError::BadPieceLength(piece_length));
+        }
+        for (path, file) in self.info.tree_files() {
+            if !is_safe_path(&path) {
+                return Err(MetainfoError::BadPath(path));
+            }
+            if let Some(target) = file.symlink_path.as_ref().filter(|t| !is_safe_path(t)) {
+                return Err(MetainfoError::BadPath(target.clone()));
+            }
+            if file.length == 0 {
+                continue;
+            }
+            let display_path = || path.iter().collect::<PathBuf>();
+            let root = file
+                .root()
+                .ok_or_else(|| MetainfoError::BadPiecesRoot(display_path()))?;
+            if file.length <= piece_length {
+                continue;
+            }
+            if let Some(layer) = self.piece_layers.get(serde_bytes::Bytes::new(&root)) {
+                let pieces = file.length.div_ceil(piece_length) as usize;
+                if layer.len() != pieces * 32
+                    || merkle::root_from_piece_layer(layer, piece_length) != root
+                {
+                    return Err(MetainfoError::BadPieceLayer(display_path()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the v1 files, minus pad files, are the same files as the v2 file tree and
+    /// start on the same piece boundaries
+    fn is_consistent_hybrid(&self) -> bool {
+        let v1_files = self
+            .files()
+            .iter()
+            .filter(|file| !file.attributes.padding)
+            .map(|file| (file.path.clone(), file.length, file.offset));
+        let v2_files = self
+            .tree_file_slices()
+            .into_iter()
+            .map(|file| (file.path, file.length, file.offset));
+        v1_files.eq(v2_files)
+    }
+}
+
+impl Info {
+    pub fn has_v1(&self) -> bool {
+        self.length.is_some() || !self.files.is_empty()
+    }
+
+    pub fn has_v2(&self) -> bool {
+        self.meta_version == Some(2)
+    }
+
+    /// A v2 single-file torrent's file tree holds just that file, named after the torrent
+    fn is_single_file_tree(&self) -> bool {
+        self.file_tree.len() == 1
+            && matches!(
+                self.file_tree.values().next(),
+                Some(FileTreeNode::File { .. })
+            )
+    }
+
+    /// The files of the v2 `file tree` with their paths, in the tree's sorted order
+    pub fn tree_files(&self) -> Vec<(Vec<String>, &TreeFile)> {
+        fn walk<'a>(
+            tree: &'a BTreeMap<String, FileTreeNode>,
+            prefix: &mut Vec<String>,
+            files: &mut Vec<(Vec<String>, &'a TreeFile)>,
+        ) {
+            for (name, node) in tree {
+                prefix.push(name.clone());
+                match node {
+                    FileTreeNode::File { file } => files.push((prefix.clone(), file)),
+                    FileTreeNode::Directory(children) => walk(children, prefix, files),
+                }
+                prefix.pop();
+            }
+        }
+        let mut files = Vec::new();
+        walk(&self.file_tree, &mut Vec::new(), &mut files);
+        files
+    }
+}
+
+impl TreeFile {
+    /// The `pieces root`, if it is a well-formed SHA-256 hash
+    pub fn root(&self) -> Option<merkle::Hash> {
+        self.pieces_root
+            .as_ref()
+            .and_then(|root| root.as_slice().try_into().ok())
+    }
+
+    /// Hashes the file's contents from `reader` and checks them against its pieces root
+    pub fn verify(&self, reader: impl Read, piece_length: u64) -> io::Result<bool> {
+        let hashes = merkle::hash_file(reader.take(self.length + 1), piece_length)?;
+        Ok(match self.root() {
+            Some(root) => hashes.root == root,
+            None => self.length == 0 && hashes.piece_layer.is_empty(),
+        })
+    }
+}
+
+/// Rejects empty, `.`/`..` and separator-containing components that would escape the download directory
+fn is_safe_path(path: &[String]) -> bool {
+    !path.is_empty()
+        && path.iter().all(|part| {
+            !part.is_empty() && part != "." && part != ".." && !part.contains(['/', '\\'])
+        })
 }
 
 pub struct TrackerRequest {
@@ -24,10 +725,20 @@ pub struct TrackerRequest {
     pub(crate) port: i32,
     pub(crate) uploaded: u32,
     pub(crate) downloaded: u32,
-    pub(crate) left: u32,
+    pub(crate) left: u64,
     pub(crate) compact: u8,
 }
 
+/// The parts of a tracker's announce response we use. `peers` is in the compact format.
+#[derive(Debug, Deserialize)]
+pub struct TrackerResponse {
+    #[serde(rename = "failure reason")]
+    pub failure_reason: Option<String>,
+    pub interval: Option<i64>,
+    #[serde(default, with = "serde_bytes")]
+    pub peers: Vec<u8>,
+}
+
 impl TrackerRequest {
     pub(crate) fn to_query_string(&self) -> String {
         format!(
@@ -43,69 +754,99 @@ impl TrackerRequest {
     }
 }
 
-pub fn hash_info(info: &Map<String, Value>) -> String {
-    let length = info
-        .get("length")
-        .expect("Length not present")
-        .as_i64()
-        .unwrap();
-    let name = info
-        .get("name")
-        .expect("Name not present")
-        .as_str()
-        .unwrap()
-        .to_string();
-    let piece_length = info
-        .get("piece length")
-        .expect("Piece length not present")
-        .as_i64()
-        .unwrap();
-    let pieces_base64 = info
-        .get("pieces")
-        .expect("Pieces not present")
-        .as_str()
-        .unwrap();
-    let pieces = general_purpose::STANDARD
-        .decode(pieces_base64)
-        .expect("Can't decode pieces");
-
-    let info_struct = Info {
-        length,
-        name,
-        piece_length,
-        pieces,
-    };
-
-    let bencoded_info = serde_bencode::to_bytes(&info_struct).expect("Couldn't bencode info");
+/// Encodes a torrent's outer dictionary with `info_bytes` spliced in verbatim as its `info`,
+/// so the info hash is kept however the dictionary was originally encoded
+pub fn encode_with_info(outer: &BTreeMap<Vec<u8>, BValue>, info_bytes: &[u8]) -> Vec<u8> {
+    let mut keys: Vec<&[u8]> = outer.keys().map(Vec::as_slice).collect();
+    if !outer.contains_key(b"info".as_slice()) {
+        keys.push(b"info");
+        keys.sort();
+    }
+    let mut out = vec![b'd'];
+    for key in keys {
+        out.extend(encode_bencoded_structure(&BValue::Bytes(key.to_vec())));
+        match key {
+            b"info" => out.extend(info_bytes),
+            _ => out.extend(encode_bencoded_structure(&outer[key])),
+        }
+    }
+    out.push(b'e');
+    out
+}
+
+/// SHA-1 of the `info` dictionary exactly as it appeared in the torrent file
+pub fn hash_info(info_bytes: &[u8]) -> String {
     let mut hasher = Sha1::new();
-    hasher.update(&bencoded_info);
+    hasher.update(info_bytes);
     let result = hasher.finalize();
     format!("{:x}", result)
 }
 
-pub fn print_hash_pieces(info: &Map<String, Value>) -> () {
-    let piece_length = info
-        .get("piece length")
-        .expect("Piece length not present")
-        .as_i64()
-        .unwrap();
-    let pieces_base64 = info
-        .get("pieces")
-        .expect("Pieces not present")
-        .as_str()
-        .unwrap();
-    let pieces = general_purpose::STANDARD
-        .decode(pieces_base64)
-        .expect("Can't decode pieces");
+pub fn print_hash_pieces(info: &Info) {
+    println!("Piece Length: {}", info.piece_length);
+    if info.has_v1() {
+        println!("Piece Hashes:");
+        for chunk in info.pieces.chunks(20) {
+            for byte in chunk {
+                print!("{:02x}", byte);
+            }
+            println!();
+        }
+    }
+    if info.has_v2() {
+        println!("Pieces Roots:");
+        for (path, file) in info.tree_files() {
+            if let Some(root) = file.root() {
+                println!("{} {}", hex::encode(root), path.join("/"));
+            }
+        }
+    }
+}
 
-    println!("Piece Length: {}", piece_length);
-    println!("Piece Hashes:");
-    let mut pieces_iter = pieces.chunks(20);
-    while let Some(chunk) = pieces_iter.next() {
-        for byte in chunk {
-            print!("{:02x}", byte);
+/// Prints the torrent's files as an indented tree with their sizes, leaving out pad files
+pub fn print_file_tree(metainfo: &Metainfo) {
+    println!("Files:");
+    println!("  {}/", metainfo.info.name);
+    let mut previous: Vec<String> = Vec::new();
+    for file in metainfo.files() {
+        if file.attributes.padding {
+            continue;
         }
-        println!();
+        let path: Vec<String> = file
+            .path
+            .iter()
+            .skip(1)
+            .map(|part| part.to_string_lossy().into_owned())
+            .collect();
+        let (name, directories) = path.split_last().expect("paths are validated");
+        let shared = previous
+            .iter()
+            .zip(directories)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, directory) in directories.iter().enumerate().skip(shared) {
+            println!("{}{}/", "  ".repeat(depth + 2), directory);
+        }
+        let description = match &file.symlink_target {
+            Some(target) if file.attributes.symlink => format!("-> {}", target.display()),
+            _ => {
+                let mut description = format!("({} bytes", file.length);
+                if file.attributes.executable {
+                    description.push_str(", executable");
+                }
+                if file.attributes.hidden {
+                    description.push_str(", hidden");
+                }
+                description + ")"
+            }
+        };
+        println!(
+            "{}{} {}",
+            "  ".repeat(directories.len() + 2),
+            name,
+            description
+        );
+        previous = directories.to_vec();
     }
 }
 
@@ -134,19 +875,308 @@ pub fn percent_encode_hex(hex_string: String) -> String {
     percent_encoded_string
 }
 
-pub fn convert_byte_array_peers(bytes: &[u8]) -> Vec<SocketAddrV4> {
-    let mut peers_array: Vec<SocketAddrV4> = vec![];
-    for group in bytes.chunks(6) {
-        let ip = format!("{}.{}.{}.{}", group[0], group[1], group[2], group[3]);
-        let port_bytes: &[u8] = &group[4..=5]; // Slice representing the 2-byte value
+#[derive(Debug, Error)]
+#[error("compact peer list is {0} bytes, which isn't a whole number of 6-byte peers")]
+pub struct BadPeers(pub usize);
 
-        if let Ok(port_array) = port_bytes.try_into() {
-            let port_value = u16::from_be_bytes(port_array);
-            let address = format!("{}:{}", ip, port_value);
-            peers_array.push(address.parse().unwrap());
-        } else {
-            eprintln!("Invalid slice length");
+/// Parses a tracker's compact peer list: 4 bytes of IPv4 address then a big-endian port each
+pub fn convert_byte_array_peers(bytes: &[u8]) -> Result<Vec<SocketAddrV4>, BadPeers> {
+    if !bytes.len().is_multiple_of(6) {
+        return Err(BadPeers(bytes.len()));
+    }
+    Ok(bytes
+        .chunks_exact(6)
+        .map(|group| {
+            let ip = Ipv4Addr::new(group[0], group[1], group[2], group[3]);
+            SocketAddrV4::new(ip, u16::from_be_bytes([group[4], group[5]]))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_compact_peers() {
+        let peers = convert_byte_array_peers(&[127, 0, 0, 1, 0x1a, 0xe1, 10, 1, 2, 3, 0, 80]);
+        assert_eq!(
+            peers.unwrap(),
+            [
+                "127.0.0.1:6881".parse().unwrap(),
+                "10.1.2.3:80".parse().unwrap()
+            ]
+        );
+        assert!(matches!(
+            convert_byte_array_peers(&[1, 2, 3, 4, 5]),
+            Err(BadPeers(5))
+        ));
+    }
+
+    #[test]
+    fn parses_sample_torrent() {
+        let metainfo = Metainfo::from_file("sample.torrent").unwrap();
+        assert_eq!(
+            metainfo.tracker_url(),
+            Some("http://bittorrent-test-tracker.codecrafters.io/announce")
+        );
+        assert_eq!(metainfo.created_by.as_deref(), Some("mktorrent 1.1"));
+        assert_eq!(metainfo.total_length(), 92063);
+        assert_eq!(metainfo.piece_hashes().count(), 3);
+        assert_eq!(
+            metainfo.info_hash_hex(),
+            "d69f91e6b2ae4c542468d1073a71d4ea13879a7f"
+        );
+    }
+
+    #[test]
+    fn accepts_url_list_as_a_single_string() {
+        let metainfo = Metainfo::from_bytes(
+            b"d4:infod6:lengthi0e4:name1:a12:piece lengthi1e6:pieces0:e8:url-list9:http://x/e",
+        )
+        .unwrap();
+        assert_eq!(metainfo.url_list, vec!["http://x/".to_string()]);
+        assert!(matches!(
+            Metainfo::from_bytes(b"d4:infod4:name1:aee"),
+            Err(MetainfoError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn info_hash_covers_the_info_bytes_as_written() {
+        // Unsorted keys and a key this client doesn't know, neither of which survives re-encoding
+        let info: &[u8] = b"d4:name1:a6:lengthi0e12:piece lengthi1e6:pieces0:9:x-unknownli1ei2eee";
+        let torrent = [&b"d8:announce9:http://x/4:info"[..], info, b"e"].concat();
+        let metainfo = Metainfo::from_bytes(&torrent).unwrap();
+        assert_eq!(metainfo.info_hash, <[u8; 20]>::from(Sha1::digest(info)));
+        assert_ne!(
+            metainfo.info_hash,
+            <[u8; 20]>::from(Sha1::digest(bencode::to_bytes(&metainfo.info).unwrap()))
+        );
+    }
+
+    fn multi_file(files: &[(&str, u64)], piece_length: u64) -> Vec<u8> {
+        let total: u64 = files.iter().map(|(_, length)| length).sum();
+        let pieces = total.div_ceil(piece_length) as usize * 20;
+        let mut bytes = b"d4:infod5:filesl".to_vec();
+        for (path, length) in files {
+            bytes.extend(format!("d6:lengthi{}e4:pathl", length).bytes());
+            for part in path.split('/') {
+                bytes.extend(format!("{}:{}", part.len(), part).bytes());
+            }
+            bytes.extend(b"ee");
+        }
+        bytes.extend(
+            format!(
+                "e4:name3:dir12:piece lengthi{}e6:pieces{}:",
+                piece_length, pieces
+            )
+            .bytes(),
+        );
+        bytes.extend(vec![0; pieces]);
+        bytes.extend(b"ee");
+        bytes
+    }
+
+    #[test]
+    fn maps_pieces_across_file_boundaries() {
+        let bytes = multi_file(&[("a", 5), ("sub/b", 0), ("sub/c", 10)], 4);
+        let metainfo = Metainfo::from_bytes(&bytes).unwrap();
+        assert!(metainfo.is_multi_file());
+        assert_eq!(metainfo.total_length(), 15);
+        assert_eq!(metainfo.piece_count(), 4);
+        assert_eq!(metainfo.piece_size(3), 3);
+        assert_eq!(metainfo.files()[2].path, Path::new("dir/sub/c"));
+        assert_eq!(metainfo.files()[2].offset, 5);
+
+        assert_eq!(
+            metainfo.piece_spans(1),
+            vec![
+                PieceSpan {
+                    file_index: 0,
+                    file_range: 4..5,
+                    piece_range: 0..1
+                },
+                PieceSpan {
+                    file_index: 2,
+                    file_range: 0..3,
+                    piece_range: 1..4
+                },
+            ]
+        );
+        assert_eq!(metainfo.piece_spans(3)[0].file_range, 7..10);
+        assert_eq!(metainfo.file_pieces(2), 1..4);
+        assert_eq!(metainfo.file_pieces(1), 0..0);
+
+        // An empty file exactly on a piece boundary belongs to no piece
+        let bytes = multi_file(&[("a", 4), ("b", 0), ("c", 4)], 4);
+        let metainfo = Metainfo::from_bytes(&bytes).unwrap();
+        assert_eq!(metainfo.piece_spans(0)[0].file_index, 0);
+        assert_eq!(metainfo.piece_spans(1).len(), 1);
+        assert_eq!(metainfo.piece_spans(1)[0].file_index, 2);
+        assert!(metainfo.piece_spans(2).is_empty());
+
+        // The layout follows edits to `info`
+        let mut metainfo = metainfo;
+        metainfo.info_mut().files[0].length = 6;
+        assert_eq!(metainfo.files()[2].offset, 6);
+        assert_eq!(metainfo.piece_count(), 3);
+    }
+
+    #[test]
+    fn rejects_paths_that_escape_the_torrent_directory() {
+        let bytes = multi_file(&[("../evil", 1)], 4);
+        assert!(matches!(
+            Metainfo::from_bytes(&bytes),
+            Err(MetainfoError::BadPath(_))
+        ));
+
+        for name in ["../../escaped", "/tmp/abs", "..", ".", ""] {
+            let bytes = format!(
+                "d4:infod6:lengthi0e4:name{}:{}12:piece lengthi1e6:pieces0:ee",
+                name.len(),
+                name
+            );
+            assert!(
+                matches!(
+                    Metainfo::from_bytes(bytes.as_bytes()),
+                    Err(MetainfoError::BadPath(path)) if path == [name]
+                ),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_zero_piece_length() {
+        let zero_piece_length = b"d4:infod6:lengthi5e4:name1:x12:piece lengthi0e6:pieces0:ee";
+        assert!(matches!(
+            Metainfo::from_bytes(zero_piece_length),
+            Err(MetainfoError::ZeroPieceLength)
+        ));
+    }
+
+    fn tree_file(data: &[u8], piece_length: u64) -> (FileTreeNode, merkle::FileHashes) {
+        let hashes = merkle::hash_file(data, piece_length).unwrap();
+        let file = TreeFile {
+            length: data.len() as u64,
+            pieces_root: Some(ByteBuf::from(hashes.root.to_vec())),
+            attr: None,
+            symlink_path: None,
+        };
+        (FileTreeNode::File { file }, hashes)
+    }
+
+    /// A v2 torrent of `d/big` (`big`) and `d/sub/small` with 16 KiB pieces
+    fn v2_metainfo(big: &[u8]) -> Metainfo {
+        let piece_length = merkle::BLOCK_SIZE as u64;
+        let (big_node, big_hashes) = tree_file(big, piece_length);
+        let (small_node, _) = tree_file(b"0123456789", piece_length);
+
+        Metainfo {
+            announce: Some("http://tracker/announce".to_string()),
+            announce_list: Vec::new(),
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            encoding: None,
+            url_list: Vec::new(),
+            info: Info {
+                name: "d".to_string(),
+                piece_length,
+                pieces: Vec::new(),
+                length: None,
+                files: Vec::new(),
+                md5sum: None,
+                private: None,
+                source: None,
+                attr: None,
+                meta_version: Some(2),
+                file_tree: BTreeMap::from([
+                    ("big".to_string(), big_node),
+                    (
+                        "sub".to_string(),
+                        FileTreeNode::Directory(BTreeMap::from([(
+                            "small".to_string(),
+                            small_node,
+                        )])),
+                    ),
+                ]),
+            },
+            piece_layers: BTreeMap::from([(
+                ByteBuf::from(big_hashes.root.to_vec()),
+                ByteBuf::from(big_hashes.piece_layer.concat()),
+            )]),
+            info_hash: [0; 20],
+            info_hash_v2: None,
+            layout: Layout::default(),
         }
     }
-    peers_array
-}
\ No newline at end of file
+
+    #[test]
+    fn parses_v2_file_trees_and_checks_piece_layers() {
+        let piece_length = merkle::BLOCK_SIZE as u64;
+        let big: Vec<u8> = (0..40000).map(|i| i as u8).collect();
+        let mut metainfo = v2_metainfo(&big);
+
+        let parsed = Metainfo::from_bytes(&bencode::to_bytes(&metainfo).unwrap()).unwrap();
+        assert_eq!(parsed.version(), TorrentVersion::V2);
+        let info_bytes = bencode::to_bytes(&metainfo.info).unwrap();
+        let info_hash_v2: [u8; 32] = Sha256::digest(&info_bytes).into();
+        assert_eq!(parsed.info_hash_v2, Some(info_hash_v2));
+        assert_eq!(parsed.swarm_hash(), info_hash_v2[..20]);
+
+        let files = parsed.files();
+        assert_eq!(files[1].path, Path::new("d/sub/small"));
+        assert_eq!(files[1].offset, 3 * piece_length);
+        assert_eq!(parsed.total_length(), 40010);
+        assert_eq!(parsed.piece_count(), 4);
+        assert_eq!(parsed.piece_size(2), 40000 - 2 * piece_length);
+
+        let (_, big_file) = parsed.info.tree_files()[0];
+        assert!(big_file.verify(&big[..], piece_length).unwrap());
+        assert!(!big_file.verify(&big[1..], piece_length).unwrap());
+
+        let layer = metainfo.piece_layers.values_mut().next().unwrap();
+        layer[0] ^= 1;
+        assert!(matches!(
+            Metainfo::from_bytes(&bencode::to_bytes(&metainfo).unwrap()),
+            Err(MetainfoError::BadPieceLayer(_))
+        ));
+    }
+
+    #[test]
+    fn hybrid_torrents_lay_out_pad_files() {
+        let mut metainfo = v2_metainfo(&[1; 40000]);
+        let entry = |path: &[&str], length, attr: Option<&str>| FileEntry {
+            length,
+            path: path.iter().map(|part| part.to_string()).collect(),
+            md5sum: None,
+            attr: attr.map(str::to_string),
+            symlink_path: None,
+        };
+        metainfo.info.files = vec![
+            entry(&["big"], 40000, None),
+            entry(&[".pad", "9152"], 9152, Some("p")),
+            entry(&["sub", "small"], 10, None),
+        ];
+        metainfo.info.pieces = vec![0; 4 * 20];
+
+        let parsed = Metainfo::from_bytes(&bencode::to_bytes(&metainfo).unwrap()).unwrap();
+        assert_eq!(parsed.version(), TorrentVersion::Hybrid);
+        assert_eq!(parsed.swarm_hash(), parsed.info_hash);
+        assert_eq!(parsed.swarm_hashes().len(), 2);
+        assert_eq!(parsed.total_length(), 40010);
+        assert!(parsed.files()[1].attributes.padding);
+        assert_eq!(parsed.piece_spans(2).len(), 2);
+        assert_eq!(parsed.piece_spans(3)[0].file_index, 2);
+
+        metainfo.info.files.remove(1);
+        metainfo.info.pieces = vec![0; 3 * 20];
+        assert!(matches!(
+            Metainfo::from_bytes(&bencode::to_bytes(&metainfo).unwrap()),
+            Err(MetainfoError::HybridMismatch)
+        ));
+    }
+}
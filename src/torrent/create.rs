@@ -0,0 +1,232 @@
+//! Building v1 `.torrent` files from a file or directory on disk
+
+use super::{FileEntry, Info, Metainfo};
+use crate::bencode;
+use crate::storage::{map_pieces, read_piece};
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MIN_PIECE_LENGTH: u64 = 16 * 1024;
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+/// Automatic piece lengths aim for at most this many pieces
+const TARGET_PIECE_COUNT: u64 = 1500;
+
+/// Everything about a new torrent that doesn't come from the files themselves
+#[derive(Debug, Clone, Default)]
+pub struct CreateOptions {
+    /// Picked from the payload size when not given
+    pub piece_length: Option<u64>,
+    /// Tracker tiers; the first tracker also becomes `announce`
+    pub trackers: Vec<Vec<String>>,
+    pub comment: Option<String>,
+    pub private: bool,
+    pub source: Option<String>,
+    pub web_seeds: Vec<String>,
+    /// Hashing threads, defaulting to the available parallelism
+    pub threads: Option<usize>,
+}
+
+/// A power of two between 16 KiB and 16 MiB that keeps the piece count near 1500
+pub fn auto_piece_length(total_length: u64) -> u64 {
+    (total_length / TARGET_PIECE_COUNT)
+        .next_power_of_two()
+        .clamp(MIN_PIECE_LENGTH, MAX_PIECE_LENGTH)
+}
+
+/// Builds the metainfo for `path`, hashing its pieces on several threads. Directories are
+/// walked recursively in sorted order, so the same tree always produces the same info hash.
+/// Symlinks inside a directory are left out, which also keeps link loops from recursing.
+pub fn create(path: &Path, options: &CreateOptions) -> io::Result<Metainfo> {
+    // `.` and `dir/..` only get a name once resolved
+    let path = &fs::canonicalize(path)?;
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| invalid_input(format!("{} has no usable name", path.display())))?
+        .to_string();
+
+    let (length, files) = if fs::metadata(path)?.is_dir() {
+        let mut files = Vec::new();
+        walk(path, &mut Vec::new(), &mut files)?;
+        if files.is_empty() {
+            return Err(invalid_input(format!("{} has no files", path.display())));
+        }
+        (None, files)
+    } else {
+        (Some(fs::metadata(path)?.len()), Vec::new())
+    };
+    let total_length = length.unwrap_or_else(|| files.iter().map(|file| file.length).sum());
+    let piece_length = match options.piece_length {
+        Some(0) => return Err(invalid_input("piece length must be positive".to_string())),
+        Some(piece_length) => piece_length,
+        None => auto_piece_length(total_length),
+    };
+
+    let mut metainfo = Metainfo {
+        announce: options.trackers.iter().flatten().next().cloned(),
+        announce_list: match options.trackers.iter().flatten().count() {
+            0 | 1 => Vec::new(),
+            _ => options.trackers.clone(),
+        },
+        comment: options.comment.clone(),
+        created_by: Some(format!(
+            "{} {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )),
+        creation_date: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|elapsed| elapsed.as_secs() as i64),
+        encoding: None,
+        url_list: options.web_seeds.clone(),
+        info: Info {
+            name,
+            piece_length,
+            pieces: Vec::new(),
+            length,
+            files,
+            md5sum: None,
+            private: options.private.then_some(true),
+            source: options.source.clone(),
+            attr: None,
+            meta_version: None,
+            file_tree: BTreeMap::new(),
+        },
+        piece_layers: BTreeMap::new(),
+        info_hash: [0; 20],
+        info_hash_v2: None,
+        layout: Default::default(),
+    };
+
+    let root = path.parent().unwrap_or(Path::new(""));
+    let hashes = map_pieces(&metainfo, options.threads, |index| {
+        Ok(Sha1::digest(read_piece(&metainfo, root, index)?))
+    })?;
+    metainfo.info_mut().pieces = hashes.concat();
+    metainfo.info_hash =
+        Sha1::digest(bencode::to_bytes(&metainfo.info).map_err(io::Error::other)?).into();
+    Ok(metainfo)
+}
+
+fn walk(directory: &Path, prefix: &mut Vec<String>, files: &mut Vec<FileEntry>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        let name = entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| invalid_input(format!("{} isn't valid UTF-8", entry.display())))?
+            .to_string();
+        prefix.push(name);
+        let metadata = fs::symlink_metadata(&entry)?;
+        if metadata.is_symlink() {
+            prefix.pop();
+            continue;
+        }
+        if metadata.is_dir() {
+            walk(&entry, prefix, files)?;
+        } else {
+            files.push(FileEntry {
+                length: metadata.len(),
+                path: prefix.clone(),
+                md5sum: None,
+                attr: None,
+                symlink_path: None,
+            });
+        }
+        prefix.pop();
+    }
+    Ok(())
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn created_torrents_reproduce_their_info_hash() {
+        let root = tempfile::tempdir().unwrap();
+        let payload = root.path().join("payload");
+        fs::create_dir_all(payload.join("sub")).unwrap();
+        fs::write(payload.join("b"), vec![1; 40_000]).unwrap();
+        fs::write(payload.join("sub/a"), vec![2; 30_000]).unwrap();
+
+        let options = CreateOptions {
+            piece_length: Some(16 * 1024),
+            trackers: vec![vec!["http://a/".to_string()], vec!["http://b/".to_string()]],
+            private: true,
+            threads: Some(3),
+            ..CreateOptions::default()
+        };
+        let metainfo = create(&payload, &options).unwrap();
+        let parsed = Metainfo::from_bytes(&bencode::to_bytes(&metainfo).unwrap()).unwrap();
+        assert_eq!(parsed.info_hash, metainfo.info_hash);
+        assert_eq!(parsed.announce.as_deref(), Some("http://a/"));
+        assert_eq!(parsed.announce_list.len(), 2);
+        assert_eq!(parsed.info.private, Some(true));
+        assert_eq!(parsed.info.files[1].path, ["sub", "a"]);
+
+        let single_threaded = CreateOptions {
+            threads: Some(1),
+            ..options
+        };
+        let pieces = create(&payload, &single_threaded).unwrap().info.pieces;
+        assert_eq!(pieces, parsed.info.pieces);
+        assert_eq!(pieces.len(), 5 * 20);
+        let third: Vec<u8> = [vec![1; 40_000 - 32_768], vec![2; 16_384 * 3 - 40_000]].concat();
+        assert_eq!(&pieces[40..60], Sha1::digest(third).as_slice());
+    }
+
+    #[test]
+    fn names_relative_paths_and_skips_symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        let payload = root.path().join("payload");
+        fs::create_dir_all(payload.join("sub")).unwrap();
+        fs::write(payload.join("sub/a"), [1; 10]).unwrap();
+        #[cfg(unix)]
+        {
+            // A loop back up the tree and a link to a file, neither of which is walked
+            std::os::unix::fs::symlink(&payload, payload.join("sub/loop")).unwrap();
+            std::os::unix::fs::symlink(payload.join("sub/a"), payload.join("b")).unwrap();
+        }
+
+        let metainfo = create(&payload.join("sub/.."), &CreateOptions::default()).unwrap();
+        assert_eq!(metainfo.info.name, "payload");
+        assert_eq!(metainfo.info.files.len(), 1);
+        assert_eq!(metainfo.info.files[0].path, ["sub", "a"]);
+        let dot = create(&payload.join("sub/."), &CreateOptions::default()).unwrap();
+        assert_eq!(dot.info.name, "sub");
+        assert_eq!(dot.info.files[0].path, ["a"]);
+    }
+
+    #[test]
+    fn empty_payloads_keep_their_pieces_key() {
+        let root = tempfile::tempdir().unwrap();
+        let payload = root.path().join("empty");
+        fs::write(&payload, []).unwrap();
+        let metainfo = create(&payload, &CreateOptions::default()).unwrap();
+        let bytes = bencode::to_bytes(&metainfo).unwrap();
+        assert!(bytes.windows(9).any(|window| window == b"6:pieces0"));
+        let parsed = Metainfo::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.info_hash, metainfo.info_hash);
+        assert_eq!(parsed.piece_count(), 0);
+    }
+
+    #[test]
+    fn piece_length_scales_with_the_payload() {
+        assert_eq!(auto_piece_length(0), MIN_PIECE_LENGTH);
+        assert_eq!(auto_piece_length(1 << 30), 1 << 20);
+        assert_eq!(auto_piece_length(1 << 45), MAX_PIECE_LENGTH);
+    }
+}
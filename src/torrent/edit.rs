@@ -0,0 +1,224 @@
+//! Rewriting fields of an existing `.torrent` file
+
+use super::{encode_with_info, MetainfoError};
+use crate::bencode::{decode_with_info_span, encode_bencoded_structure, BValue};
+use std::collections::BTreeMap;
+
+/// Changes to make to a torrent. `None` and empty lists leave a field alone; an empty string
+/// removes the field.
+#[derive(Debug, Clone, Default)]
+pub struct TorrentEdit {
+    /// Replaces every tracker with these tiers
+    pub trackers: Option<Vec<Vec<String>>>,
+    /// Trackers to append, each in a tier of its own
+    pub add_trackers: Vec<String>,
+    pub remove_trackers: Vec<String>,
+    pub comment: Option<String>,
+    /// Replaces the web seeds; an empty list removes them
+    pub web_seeds: Option<Vec<String>>,
+    /// Lives in `info`, so changing it changes the info hash
+    pub source: Option<String>,
+    /// Lives in `info`, so changing it changes the info hash
+    pub private: Option<bool>,
+}
+
+impl TorrentEdit {
+    pub fn touches_info(&self) -> bool {
+        self.source.is_some() || self.private.is_some()
+    }
+
+    fn touches_trackers(&self) -> bool {
+        self.trackers.is_some() || !self.add_trackers.is_empty() || !self.remove_trackers.is_empty()
+    }
+}
+
+/// The rewritten torrent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edited {
+    pub bytes: Vec<u8>,
+    /// The `info` dictionary as it now appears in `bytes`
+    pub info_bytes: Vec<u8>,
+    pub info_changed: bool,
+}
+
+/// Applies `edit` to the torrent in `bytes`. Unless the edit touches `info`, the original
+/// `info` bytes are copied through untouched, so the info hash stays the same even for
+/// torrents that weren't canonically encoded.
+pub fn apply(bytes: &[u8], edit: &TorrentEdit) -> Result<Edited, MetainfoError> {
+    let (value, info_span) = decode_with_info_span(bytes)?;
+    let info_span = info_span.ok_or(MetainfoError::MissingInfo)?;
+    let BValue::Dict(mut outer) = value else {
+        return Err(MetainfoError::MissingInfo);
+    };
+
+    if edit.touches_trackers() {
+        set_trackers(&mut outer, edit);
+    }
+    if let Some(comment) = &edit.comment {
+        set_string(&mut outer, b"comment", comment);
+    }
+    if let Some(web_seeds) = &edit.web_seeds {
+        set_list(&mut outer, b"url-list", web_seeds);
+    }
+
+    let mut info_bytes = bytes[info_span].to_vec();
+    let mut info_changed = false;
+    if edit.touches_info() {
+        let Some(BValue::Dict(info)) = outer.get_mut(b"info".as_slice()) else {
+            return Err(MetainfoError::MissingInfo);
+        };
+        if let Some(source) = &edit.source {
+            set_string(info, b"source", source);
+        }
+        match edit.private {
+            Some(true) => {
+                info.insert(b"private".to_vec(), BValue::Int(1));
+            }
+            Some(false) => {
+                info.remove(b"private".as_slice());
+            }
+            None => {}
+        }
+        let edited_info = encode_bencoded_structure(&BValue::Dict(info.clone()));
+        info_changed = edited_info != info_bytes;
+        info_bytes = edited_info;
+    }
+
+    Ok(Edited {
+        bytes: encode_with_info(&outer, &info_bytes),
+        info_bytes,
+        info_changed,
+    })
+}
+
+/// Rewrites `announce` and `announce-list` from the existing tiers and the tracker edits
+fn set_trackers(outer: &mut BTreeMap<Vec<u8>, BValue>, edit: &TorrentEdit) {
+    let had_list = outer.contains_key(b"announce-list".as_slice());
+    let mut tiers: Vec<Vec<String>> = match &edit.trackers {
+        Some(trackers) => trackers.clone(),
+        None => current_tiers(outer),
+    };
+    for tier in &mut tiers {
+        tier.retain(|url| !edit.remove_trackers.contains(url));
+    }
+    for url in &edit.add_trackers {
+        if !tiers.iter().flatten().any(|existing| existing == url) {
+            tiers.push(vec![url.clone()]);
+        }
+    }
+    tiers.retain(|tier| !tier.is_empty());
+
+    let first = tiers.iter().flatten().next().map_or("", String::as_str);
+    set_string(outer, b"announce", first);
+    let tracker_count = tiers.iter().flatten().count();
+    if tracker_count > 1 || (had_list && tracker_count > 0) {
+        let tiers = tiers
+            .iter()
+            .map(|tier| BValue::List(tier.iter().map(|url| bytes_value(url)).collect()))
+            .collect();
+        outer.insert(b"announce-list".to_vec(), BValue::List(tiers));
+    } else {
+        outer.remove(b"announce-list".as_slice());
+    }
+}
+
+fn current_tiers(outer: &BTreeMap<Vec<u8>, BValue>) -> Vec<Vec<String>> {
+    let tier_urls = |tier: &BValue| -> Vec<String> {
+        match tier {
+            BValue::List(urls) => urls
+                .iter()
+                .filter_map(BValue::as_str)
+                .map(str::to_string)
+                .collect(),
+            _ => Vec::new(),
+        }
+    };
+    match outer.get(b"announce-list".as_slice()) {
+        Some(BValue::List(tiers)) => tiers.iter().map(tier_urls).collect(),
+        _ => outer
+            .get(b"announce".as_slice())
+            .and_then(BValue::as_str)
+            .map(|announce| vec![vec![announce.to_string()]])
+            .unwrap_or_default(),
+    }
+}
+
+fn bytes_value(text: &str) -> BValue {
+    BValue::Bytes(text.as_bytes().to_vec())
+}
+
+/// Sets a string field, or removes it when `value` is empty
+fn set_string(dict: &mut BTreeMap<Vec<u8>, BValue>, key: &[u8], value: &str) {
+    if value.is_empty() {
+        dict.remove(key);
+    } else {
+        dict.insert(key.to_vec(), bytes_value(value));
+    }
+}
+
+/// Sets a list of strings, or removes it when `values` is empty
+fn set_list(dict: &mut BTreeMap<Vec<u8>, BValue>, key: &[u8], values: &[String]) {
+    if values.is_empty() {
+        dict.remove(key);
+    } else {
+        let values = values.iter().map(|value| bytes_value(value)).collect();
+        dict.insert(key.to_vec(), BValue::List(values));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::Metainfo;
+
+    #[test]
+    fn outer_edits_keep_the_info_bytes() {
+        // `info` keys out of order: re-encoding it would change the hash
+        let original =
+            b"d8:announce9:http://a/4:infod4:name1:x6:lengthi0e12:piece lengthi1e6:pieces0:ee";
+        let before = Metainfo::from_bytes(original).unwrap();
+
+        let edit = TorrentEdit {
+            add_trackers: vec!["http://b/".to_string()],
+            comment: Some("hi".to_string()),
+            web_seeds: Some(vec!["http://seed/".to_string()]),
+            ..TorrentEdit::default()
+        };
+        let edited = apply(original, &edit).unwrap();
+        assert!(!edited.info_changed);
+        let after = Metainfo::from_bytes(&edited.bytes).unwrap();
+        assert_eq!(after.info_hash, before.info_hash);
+        assert_eq!(after.announce_list, [["http://a/"], ["http://b/"]]);
+        assert_eq!(after.comment.as_deref(), Some("hi"));
+        assert_eq!(after.url_list, ["http://seed/"]);
+
+        let edit = TorrentEdit {
+            remove_trackers: vec!["http://a/".to_string()],
+            comment: Some(String::new()),
+            ..TorrentEdit::default()
+        };
+        let after = Metainfo::from_bytes(&apply(&edited.bytes, &edit).unwrap().bytes).unwrap();
+        assert_eq!(after.announce.as_deref(), Some("http://b/"));
+        assert_eq!(after.announce_list, [["http://b/"]]);
+        assert_eq!(after.comment, None);
+    }
+
+    #[test]
+    fn info_edits_change_the_hash() {
+        let original = b"d4:infod6:lengthi0e4:name1:x12:piece lengthi1e6:pieces0:ee";
+        let edit = TorrentEdit {
+            private: Some(true),
+            source: Some("src".to_string()),
+            ..TorrentEdit::default()
+        };
+        let edited = apply(original, &edit).unwrap();
+        assert!(edited.info_changed);
+        let after = Metainfo::from_bytes(&edited.bytes).unwrap();
+        assert_eq!(after.info.private, Some(true));
+        assert_eq!(after.info.source.as_deref(), Some("src"));
+        assert_ne!(
+            after.info_hash,
+            Metainfo::from_bytes(original).unwrap().info_hash
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! SHA-256 merkle trees over 16 KiB blocks, as used by v2 torrents (BEP 52)
+
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+
+/// Leaves of the tree are hashes of blocks this size; only a file's last block may be shorter
+pub const BLOCK_SIZE: usize = 16 * 1024;
+
+pub type Hash = [u8; 32];
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Root of the tree over `leaves`, padded with `pad` up to the next power of two
+pub fn root(leaves: &[Hash], pad: Hash) -> Hash {
+    let mut layer = leaves.to_vec();
+    layer.resize(leaves.len().next_power_of_two().max(1), pad);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+    }
+    layer[0]
+}
+
+/// Root of a subtree whose `leaves` leaves are all zero, which is what pads a piece layer
+pub fn pad_hash(leaves: usize) -> Hash {
+    let mut hash = [0; 32];
+    let mut width = 1;
+    while width < leaves {
+        hash = hash_pair(&hash, &hash);
+        width *= 2;
+    }
+    hash
+}
+
+/// Recomputes a file's `pieces root` from its `piece layers` entry
+pub fn root_from_piece_layer(layer: &[u8], piece_length: u64) -> Hash {
+    let hashes: Vec<Hash> = layer
+        .chunks_exact(32)
+        .map(|hash| hash.try_into().expect("chunks are 32 bytes"))
+        .collect();
+    root(&hashes, pad_hash(piece_length as usize / BLOCK_SIZE))
+}
+
+/// The hashes of one file's contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHashes {
+    /// The file's `pieces root`; all zeros for an empty file
+    pub root: Hash,
+    /// One hash per piece. A file no longer than a piece has just its root here, which is
+    /// why torrents only store `piece layers` for files longer than a piece.
+    pub piece_layer: Vec<Hash>,
+}
+
+/// Hashes a file's contents from `reader` in blocks, building both its piece layer and root
+pub fn hash_file(mut reader: impl Read, piece_length: u64) -> io::Result<FileHashes> {
+    let blocks_per_piece = piece_length as usize / BLOCK_SIZE;
+    let mut blocks: Vec<Hash> = Vec::new();
+    let mut buffer = vec![0; BLOCK_SIZE];
+    loop {
+        let mut filled = 0;
+        while filled < BLOCK_SIZE {
+            match reader.read(&mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if filled > 0 {
+            blocks.push(Sha256::digest(&buffer[..filled]).into());
+        }
+        if filled < BLOCK_SIZE {
+            break;
+        }
+    }
+
+    if blocks.is_empty() {
+        return Ok(FileHashes {
+            root: [0; 32],
+            piece_layer: Vec::new(),
+        });
+    }
+    if blocks.len() <= blocks_per_piece {
+        let root = root(&blocks, [0; 32]);
+        return Ok(FileHashes {
+            root,
+            piece_layer: vec![root],
+        });
+    }
+    let piece_layer: Vec<Hash> = blocks
+        .chunks(blocks_per_piece)
+        .map(|piece| {
+            let mut piece = piece.to_vec();
+            piece.resize(blocks_per_piece, [0; 32]);
+            root(&piece, [0; 32])
+        })
+        .collect();
+    Ok(FileHashes {
+        root: root(&piece_layer, pad_hash(blocks_per_piece)),
+        piece_layer,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn piece_layer_reproduces_the_root() {
+        let piece_length = 4 * BLOCK_SIZE as u64;
+        let data: Vec<u8> = (0..9 * BLOCK_SIZE + 100).map(|i| i as u8).collect();
+        let hashes = hash_file(&data[..], piece_length).unwrap();
+        assert_eq!(hashes.piece_layer.len(), 3);
+
+        let layer: Vec<u8> = hashes.piece_layer.concat();
+        assert_eq!(root_from_piece_layer(&layer, piece_length), hashes.root);
+
+        let leaves: Vec<Hash> = data
+            .chunks(BLOCK_SIZE)
+            .map(|block| Sha256::digest(block).into())
+            .collect();
+        assert_eq!(root(&leaves, [0; 32]), hashes.root);
+    }
+
+    #[test]
+    fn small_files_are_padded_to_their_own_block_count() {
+        let data = vec![7; BLOCK_SIZE + 1];
+        let hashes = hash_file(&data[..], 16 * BLOCK_SIZE as u64).unwrap();
+        let first: Hash = Sha256::digest(&data[..BLOCK_SIZE]).into();
+        let second: Hash = Sha256::digest(&data[BLOCK_SIZE..]).into();
+        assert_eq!(hashes.root, hash_pair(&first, &second));
+        assert_eq!(
+            hash_file(&[][..], 16 * BLOCK_SIZE as u64).unwrap().root,
+            [0; 32]
+        );
+    }
+}
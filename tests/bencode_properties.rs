@@ -0,0 +1,95 @@
+//! Property tests over arbitrary bencode trees and arbitrary bytes
+
+use bittorrent_starter_rust::bencode::{
+    decode_bencoded_structure, decode_borrowed, decode_document, encode_bencoded_structure, BValue,
+    Mode, Progress, StreamDecoder,
+};
+use proptest::collection::{btree_map, vec};
+use proptest::prelude::*;
+
+/// Byte strings that look like what torrents carry: short text keys, binary hashes and blobs
+fn arb_bytes() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        "[a-z ._-]{0,12}".prop_map(String::into_bytes),
+        vec(any::<u8>(), 20),
+        vec(any::<u8>(), 0..64),
+    ]
+}
+
+fn arb_bvalue() -> impl Strategy<Value = BValue> {
+    let leaf = prop_oneof![
+        any::<i64>().prop_map(BValue::Int),
+        (-10i64..10).prop_map(BValue::Int),
+        arb_bytes().prop_map(BValue::Bytes),
+    ];
+    leaf.prop_recursive(6, 256, 10, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..10).prop_map(BValue::List),
+            btree_map(arb_bytes(), inner, 0..10).prop_map(BValue::Dict),
+        ]
+    })
+}
+
+/// Canonical encodings, sometimes mangled so the decoder also sees near-miss input
+fn arb_encoded() -> impl Strategy<Value = Vec<u8>> {
+    (arb_bvalue(), any::<usize>(), any::<u8>(), 0usize..3).prop_map(
+        |(value, index, byte, mutation)| {
+            let mut encoded = encode_bencoded_structure(&value);
+            let index = index % encoded.len();
+            match mutation {
+                0 => encoded[index] = byte,
+                1 => encoded.truncate(index),
+                _ => encoded.insert(index, byte),
+            }
+            encoded
+        },
+    )
+}
+
+proptest! {
+    #[test]
+    fn decode_round_trips_through_encode(value in arb_bvalue()) {
+        let encoded = encode_bencoded_structure(&value);
+        let decoded = decode_document(&encoded, Mode::Strict).unwrap();
+        prop_assert!(decoded.warnings.is_empty());
+        prop_assert_eq!(&decoded.value, &value);
+        prop_assert_eq!(encode_bencoded_structure(&decoded.value), encoded);
+    }
+
+    #[test]
+    fn decoders_never_panic_and_agree(input in prop_oneof![arb_encoded(), vec(any::<u8>(), 0..256)]) {
+        let owned = decode_bencoded_structure(&input);
+        let borrowed = decode_borrowed(&input);
+        prop_assert_eq!(owned.is_ok(), borrowed.is_ok());
+        if let (Ok(owned), Ok(borrowed)) = (&owned, &borrowed) {
+            prop_assert_eq!(owned, &borrowed.to_owned_value());
+            let reencoded = encode_bencoded_structure(owned);
+            prop_assert_eq!(&decode_bencoded_structure(&reencoded).unwrap(), owned);
+        }
+    }
+
+    #[test]
+    fn stream_decoder_matches_one_shot_decoding(value in arb_bvalue(), chunk in 1usize..17) {
+        let encoded = encode_bencoded_structure(&value);
+        let mut stream = StreamDecoder::new();
+        let mut result = None;
+        for piece in encoded.chunks(chunk) {
+            stream.feed(piece);
+            match stream.decode().unwrap() {
+                Progress::NeedMore(n) => prop_assert!(n > 0),
+                Progress::Complete(decoded, consumed) => {
+                    result = Some((decoded, consumed));
+                    break;
+                }
+            }
+        }
+        prop_assert_eq!(result, Some((value, encoded.len())));
+    }
+}
+
+#[test]
+fn sample_torrent_is_canonical() {
+    let bytes = std::fs::read("sample.torrent").unwrap();
+    let decoded = decode_document(&bytes, Mode::Strict).unwrap();
+    assert_eq!(encode_bencoded_structure(&decoded.value), bytes);
+}
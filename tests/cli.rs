@@ -0,0 +1,46 @@
+//! The binary's exit status, which scripts rely on to notice a failed command
+
+use std::process::{Command, Output};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bittorrent-starter-rust"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn succeeds_when_the_command_does() {
+    let output = run(&["decode", "i52e"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"52\n");
+    assert!(run(&["info", "--validate", "sample.torrent"])
+        .status
+        .success());
+}
+
+#[test]
+fn fails_when_the_command_does() {
+    let root = tempfile::tempdir().unwrap();
+    let missing = root.path().join("missing.torrent");
+    // Trailing bytes make the torrent non-canonical, though info still reads it
+    let mut bytes = std::fs::read("sample.torrent").unwrap();
+    bytes.extend(b"junk");
+    let trailing = root.path().join("trailing.torrent");
+    std::fs::write(&trailing, bytes).unwrap();
+
+    for args in [
+        vec!["bogus"],
+        vec!["download", "sample.torrent"],
+        vec!["handshake", "sample.torrent", "notanaddr"],
+        vec!["handshake", "sample.torrent"],
+        vec!["peers"],
+        vec!["decode", "i52"],
+        vec!["info", missing.to_str().unwrap()],
+        vec!["info", "--validate", trailing.to_str().unwrap()],
+    ] {
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: "));
+    }
+}